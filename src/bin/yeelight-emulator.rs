@@ -0,0 +1,43 @@
+use yeelight::emulator::{Emulator, Scenario};
+
+fn run(listen: &str, scenario: Option<&String>) -> Result<(), Box<dyn std::error::Error>> {
+    let emulator = Emulator::bind(listen)?;
+    if let Some(path) = scenario {
+        emulator.set_scenario(Scenario::load(path)?);
+    }
+    log::info!("Listening on {}", emulator.local_addr()?);
+    emulator.run()?;
+    Ok(())
+}
+
+fn main() -> std::process::ExitCode {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let matches = clap::Command::new("yeelight-emulator")
+        .about("Emulate a Yeelight lamp for local testing")
+        .arg(
+            clap::Arg::new("listen")
+                .long("listen")
+                .value_name("ADDR")
+                .default_value("127.0.0.1:55443")
+                .help("Address to accept connections on"),
+        )
+        .arg(
+            clap::Arg::new("scenario")
+                .long("scenario")
+                .value_name("FILE")
+                .help("JSON file with faults to inject"),
+        )
+        .get_matches();
+
+    match run(
+        matches.get_one::<String>("listen").expect("default"),
+        matches.get_one::<String>("scenario"),
+    ) {
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            std::process::ExitCode::from(1)
+        }
+        Ok(_) => std::process::ExitCode::from(0),
+    }
+}
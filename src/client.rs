@@ -0,0 +1,354 @@
+use std::{
+    io::{BufRead, Write},
+    net::ToSocketAddrs,
+};
+
+use crate::{
+    commands::Commands,
+    flow::FlowError,
+    props::{Properties, PropertyError, PROPERTY_NAMES},
+    protocol::{CronJob, DeviceError, Incoming, Message, Notification, Param},
+    ratelimit::{RateLimit, RateLimitError},
+};
+
+/// An error returned by [`Client`] commands.
+#[derive(Debug, thiserror::Error)]
+pub enum CommandError {
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid response: {0}")]
+    InvalidResponse(#[from] serde_json::Error),
+    #[error("connection closed by device")]
+    ConnectionClosed,
+    #[error("invalid flow: {0}")]
+    InvalidFlow(#[from] FlowError),
+    #[error("{0}")]
+    RateLimited(#[from] RateLimitError),
+    #[error("invalid properties: {0}")]
+    InvalidProperties(#[from] PropertyError),
+    #[error("device returned error {code}: {message}")]
+    Device { code: i32, message: String },
+}
+
+/// A connection to a single lamp.
+///
+/// Commands are sent one at a time and each call waits for the reply with the matching id.
+/// Notifications received in the meantime are queued and can be read with
+/// [`Client::take_notifications`].
+#[derive(Debug)]
+pub struct Client {
+    stream: bufstream::BufStream<std::net::TcpStream>,
+    next_id: u16,
+    notifications: std::collections::VecDeque<Notification>,
+    buffer: Vec<u8>,
+    rate_limit: Option<(RateLimit, String)>,
+}
+
+/// Connection settings for [`Client`].
+///
+/// ```
+/// use std::time::Duration;
+///
+/// let config = yeelight::ClientConfig::new()
+///     .port(55443)
+///     .connect_attempts(3)
+///     .connect_timeout(Duration::from_secs(1));
+/// ```
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    port: u16,
+    connect_attempts: u32,
+    connect_timeout: std::time::Duration,
+    read_timeout: std::time::Duration,
+    write_timeout: std::time::Duration,
+    rate_limit: Option<RateLimit>,
+}
+
+/// Sockets reject a zero timeout.
+const MIN_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(1);
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        ClientConfig {
+            port: 55443,
+            connect_attempts: 150 / 3,
+            connect_timeout: std::time::Duration::from_millis(300),
+            read_timeout: std::time::Duration::from_millis(200),
+            write_timeout: std::time::Duration::from_millis(200),
+            rate_limit: Some(RateLimit::default()),
+        }
+    }
+}
+
+impl ClientConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// Sets how many times connecting is tried before giving up. Values below 1 are treated
+    /// as 1.
+    pub fn connect_attempts(mut self, attempts: u32) -> Self {
+        self.connect_attempts = attempts.max(1);
+        self
+    }
+
+    /// Sets the timeout of a single connection attempt. Values below 1ms are treated as 1ms.
+    pub fn connect_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.connect_timeout = timeout.max(MIN_TIMEOUT);
+        self
+    }
+
+    /// Sets how long to wait for a reply before the command is re-sent once. Values below 1ms
+    /// are treated as 1ms.
+    pub fn read_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.read_timeout = timeout.max(MIN_TIMEOUT);
+        self
+    }
+
+    /// Sets the timeout for sending a command. Values below 1ms are treated as 1ms.
+    pub fn write_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.write_timeout = timeout.max(MIN_TIMEOUT);
+        self
+    }
+
+    /// Sets the command budget. By default it is 60 commands per minute per lamp, and
+    /// commands wait until they fit into it.
+    pub fn rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.rate_limit = Some(rate_limit);
+        self
+    }
+
+    /// Sends commands without checking the budget, leaving it to the lamp to reject them.
+    pub fn no_rate_limit(mut self) -> Self {
+        self.rate_limit = None;
+        self
+    }
+
+    /// Gives up after the first failed connection attempt.
+    pub fn fail_fast(self) -> Self {
+        self.connect_attempts(1)
+    }
+}
+
+fn connect_with_retries(
+    host: &str,
+    port: u16,
+    max_attempts: u32,
+    timeout: std::time::Duration,
+) -> std::io::Result<std::net::TcpStream> {
+    for attempt in 0..max_attempts {
+        let socket_addr = (host, port).to_socket_addrs()?.next().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("unable to resolve hostname {}", host),
+            )
+        })?;
+        match std::net::TcpStream::connect_timeout(&socket_addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => {
+                log::debug!("Failed to connect to {}:{}: {}", host, port, e);
+                if attempt == max_attempts - 1 {
+                    return Err(e);
+                }
+            }
+        }
+    }
+    unreachable!()
+}
+
+impl Client {
+    /// Connects to the lamp at `host:port` with the default settings, retrying for up to 15
+    /// seconds.
+    pub fn connect(host: &str, port: u16) -> std::io::Result<Self> {
+        Self::connect_with(host, &ClientConfig::new().port(port))
+    }
+
+    /// Connects to the lamp at `host` with the given settings.
+    pub fn connect_with(host: &str, config: &ClientConfig) -> std::io::Result<Self> {
+        log::debug!("Connecting to {}:{}...", host, config.port);
+        let start = std::time::Instant::now();
+        let tcp_stream = connect_with_retries(
+            host,
+            config.port,
+            config.connect_attempts,
+            config.connect_timeout,
+        )?;
+        log::debug!("Connected in {:?}", start.elapsed());
+        tcp_stream.set_read_timeout(Some(config.read_timeout))?;
+        tcp_stream.set_write_timeout(Some(config.write_timeout))?;
+        let stream = bufstream::BufStream::new(tcp_stream);
+        Ok(Client {
+            stream,
+            next_id: 1,
+            notifications: std::collections::VecDeque::new(),
+            buffer: Vec::new(),
+            rate_limit: config
+                .rate_limit
+                .clone()
+                .map(|rate_limit| (rate_limit, format!("{}:{}", host, config.port))),
+        })
+    }
+
+    /// Returns the local address of the connection, i.e. the address the lamp reaches us on.
+    pub fn local_addr(&self) -> std::io::Result<std::net::SocketAddr> {
+        self.stream.get_ref().local_addr()
+    }
+
+    /// Sends a raw command and returns the `result` array of its reply.
+    ///
+    /// The command is checked against the rate limit first; a re-send after a read timeout
+    /// is not counted.
+    pub fn send_command(
+        &mut self,
+        method: &str,
+        params: Vec<Param>,
+    ) -> Result<Vec<serde_json::Value>, CommandError> {
+        let message = Message {
+            id: self.next_id,
+            method: method.to_string(),
+            params,
+        };
+        // Ids start at 1, so 0 is skipped when they wrap around.
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        let json_message = serde_json::to_string(&message)?;
+        if let Some((rate_limit, key)) = &self.rate_limit {
+            rate_limit.acquire(key)?;
+        }
+        log::debug!("Sending: {}", json_message);
+        let start = std::time::Instant::now();
+        self.stream
+            .write_all(format!("{}\r\n", json_message).as_bytes())?;
+        self.stream.flush()?;
+
+        let mut resent = false;
+        loop {
+            let incoming = match self.read_incoming() {
+                Err(CommandError::Io(ref e))
+                    if e.kind() == std::io::ErrorKind::WouldBlock && !resent =>
+                {
+                    log::debug!("Re-sending: {}", json_message);
+                    self.stream
+                        .write_all(format!("{}\r\n", json_message).as_bytes())?;
+                    self.stream.flush()?;
+                    resent = true;
+                    continue;
+                }
+                result => result?,
+            };
+
+            match incoming {
+                Incoming::Notification(notification) => {
+                    self.notifications.push_back(notification);
+                }
+                Incoming::Response(response) if response.id == message.id => {
+                    log::debug!("Got reply to {} after {:?}", message.id, start.elapsed());
+                    return response
+                        .into_result()
+                        .map_err(|DeviceError { code, message }| CommandError::Device {
+                            code,
+                            message,
+                        });
+                }
+                Incoming::Response(response) => {
+                    log::debug!(
+                        "Dropping stale reply to {} while waiting for {}",
+                        response.id,
+                        message.id
+                    );
+                }
+            }
+        }
+    }
+
+    /// Reads the next non-empty line from the lamp. A partially read line is kept until the
+    /// next call, so read timeouts don't lose data.
+    fn read_incoming(&mut self) -> Result<Incoming, CommandError> {
+        loop {
+            if self.stream.read_until(b'\n', &mut self.buffer)? == 0 {
+                return Err(CommandError::ConnectionClosed);
+            }
+            if self.buffer.last() != Some(&b'\n') {
+                continue;
+            }
+
+            let line = std::mem::take(&mut self.buffer);
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            log::debug!("Received: {}", line);
+            return Ok(serde_json::from_str(line)?);
+        }
+    }
+
+    /// Drains the notifications received so far.
+    pub fn take_notifications(&mut self) -> impl Iterator<Item = Notification> + '_ {
+        self.notifications.drain(..)
+    }
+
+    /// Blocks until the lamp sends a notification. Notifications queued while waiting for
+    /// replies are returned first.
+    pub fn next_notification(&mut self) -> Result<Notification, CommandError> {
+        if let Some(notification) = self.notifications.pop_front() {
+            return Ok(notification);
+        }
+        loop {
+            match self.read_incoming() {
+                Err(CommandError::Io(ref e))
+                    if e.kind() == std::io::ErrorKind::WouldBlock
+                        || e.kind() == std::io::ErrorKind::TimedOut => {}
+                Err(e) => return Err(e),
+                Ok(Incoming::Notification(notification)) => return Ok(notification),
+                Ok(Incoming::Response(response)) => {
+                    log::debug!("Dropping unexpected reply to {}", response.id);
+                }
+            }
+        }
+    }
+
+    /// Reads the raw values of the given properties. Unsupported properties are returned as
+    /// empty strings.
+    pub fn get_prop(&mut self, names: &[&str]) -> Result<Vec<String>, CommandError> {
+        let result = self.send_command(
+            "get_prop",
+            names
+                .iter()
+                .map(|name| Param::Str(name.to_string()))
+                .collect(),
+        )?;
+        Ok(serde_json::from_value(serde_json::Value::Array(result))?)
+    }
+
+    /// Reads all properties listed in [`PROPERTY_NAMES`].
+    pub fn get_properties(&mut self) -> Result<Properties, CommandError> {
+        let values = self.get_prop(&PROPERTY_NAMES)?;
+        Ok(Properties::from_values(&PROPERTY_NAMES, &values)?)
+    }
+
+    /// Returns the time left until the power-off timer turns the lamp off, or `None` if no
+    /// timer is set. The lamp reports it in whole minutes.
+    pub fn get_timer(&mut self) -> Result<Option<std::time::Duration>, CommandError> {
+        let result = self.send_command("cron_get", vec![Param::Uint8(0)])?;
+        let Some(job) = result.into_iter().next() else {
+            return Ok(None);
+        };
+        let job: CronJob = serde_json::from_value(job)?;
+        Ok((job.delay > 0).then(|| std::time::Duration::from_secs(job.delay * 60)))
+    }
+}
+
+impl Commands for Client {
+    fn send_command(
+        &mut self,
+        method: &str,
+        params: Vec<Param>,
+    ) -> Result<Vec<serde_json::Value>, CommandError> {
+        Client::send_command(self, method, params)
+    }
+}
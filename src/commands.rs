@@ -0,0 +1,228 @@
+use crate::{
+    client::CommandError,
+    flow::Flow,
+    name::encode_name,
+    protocol::{AdjustAction, AdjustProp, Light, Mode, Param, Power, Transition},
+    scene::Scene,
+};
+
+/// Typed lamp commands on top of a raw `send_command`.
+///
+/// Implemented by [`crate::Client`], which waits for each reply, and by
+/// [`crate::MusicSession`], which doesn't.
+pub trait Commands {
+    /// Sends a raw command and returns the `result` array of its reply, or an empty array if
+    /// the connection doesn't get replies.
+    fn send_command(
+        &mut self,
+        method: &str,
+        params: Vec<Param>,
+    ) -> Result<Vec<serde_json::Value>, CommandError>;
+
+    /// Turns the light on or off. `mode` selects the mode the light is switched into when it
+    /// is turned on.
+    fn set_power(
+        &mut self,
+        light: Light,
+        power: Power,
+        mode: Option<Mode>,
+        transition: Transition,
+    ) -> Result<(), CommandError> {
+        let mut params = vec![Param::Str(power.as_str().to_string())];
+        params.extend(transition.params());
+        if let Some(mode) = mode {
+            params.push(Param::Uint8(mode as u8));
+        }
+        self.send_command(&light.method("set_power"), params)?;
+        Ok(())
+    }
+
+    /// Turns the light off if it is on and on if it is off.
+    fn toggle(&mut self, light: Light) -> Result<(), CommandError> {
+        self.send_command(&light.method("toggle"), vec![])?;
+        Ok(())
+    }
+
+    /// Toggles the main and the ambient light together, following the power state of the main
+    /// light.
+    fn toggle_device(&mut self) -> Result<(), CommandError> {
+        self.send_command("dev_toggle", vec![])?;
+        Ok(())
+    }
+
+    /// Sets the brightness (1-100) of the light.
+    fn set_bright(
+        &mut self,
+        light: Light,
+        bright: u8,
+        transition: Transition,
+    ) -> Result<(), CommandError> {
+        let mut params = vec![Param::Uint8(bright)];
+        params.extend(transition.params());
+        self.send_command(&light.method("set_bright"), params)?;
+        Ok(())
+    }
+
+    /// Sets the color temperature of the light in Kelvin (see [`crate::CT_RANGE`]).
+    fn set_ct(
+        &mut self,
+        light: Light,
+        ct: u16,
+        transition: Transition,
+    ) -> Result<(), CommandError> {
+        let mut params = vec![Param::Uint16(ct)];
+        params.extend(transition.params());
+        self.send_command(&light.method("set_ct_abx"), params)?;
+        Ok(())
+    }
+
+    /// Sets the color of the light as `0xRRGGBB`.
+    fn set_rgb(
+        &mut self,
+        light: Light,
+        rgb: u32,
+        transition: Transition,
+    ) -> Result<(), CommandError> {
+        let mut params = vec![Param::Uint32(rgb)];
+        params.extend(transition.params());
+        self.send_command(&light.method("set_rgb"), params)?;
+        Ok(())
+    }
+
+    /// Sets the hue (0-359) and saturation (0-100) of the light.
+    fn set_hsv(
+        &mut self,
+        light: Light,
+        hue: u16,
+        sat: u8,
+        transition: Transition,
+    ) -> Result<(), CommandError> {
+        let mut params = vec![Param::Uint16(hue), Param::Uint8(sat)];
+        params.extend(transition.params());
+        self.send_command(&light.method("set_hsv"), params)?;
+        Ok(())
+    }
+
+    /// Changes a property by a step the lamp chooses.
+    fn set_adjust(
+        &mut self,
+        light: Light,
+        action: AdjustAction,
+        prop: AdjustProp,
+    ) -> Result<(), CommandError> {
+        self.send_command(
+            &light.method("set_adjust"),
+            vec![
+                Param::Str(action.as_str().to_string()),
+                Param::Str(prop.as_str().to_string()),
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Changes the brightness by `percentage` (-100-100) points.
+    ///
+    /// The adjust methods always change smoothly, so only the duration of `transition` is
+    /// used.
+    fn adjust_bright(
+        &mut self,
+        light: Light,
+        percentage: i8,
+        transition: Transition,
+    ) -> Result<(), CommandError> {
+        self.send_command(
+            &light.method("adjust_bright"),
+            adjust_params(percentage, transition),
+        )?;
+        Ok(())
+    }
+
+    /// Changes the color temperature by `percentage` (-100-100) of [`crate::CT_RANGE`].
+    fn adjust_ct(
+        &mut self,
+        light: Light,
+        percentage: i8,
+        transition: Transition,
+    ) -> Result<(), CommandError> {
+        self.send_command(
+            &light.method("adjust_ct"),
+            adjust_params(percentage, transition),
+        )?;
+        Ok(())
+    }
+
+    /// Moves the color to the next one of the lamp's built-in sequence. The lamps ignore
+    /// `percentage`.
+    fn adjust_color(
+        &mut self,
+        light: Light,
+        percentage: i8,
+        transition: Transition,
+    ) -> Result<(), CommandError> {
+        self.send_command(
+            &light.method("adjust_color"),
+            adjust_params(percentage, transition),
+        )?;
+        Ok(())
+    }
+
+    /// Turns the light on and applies `scene` in one command, so that the light doesn't show
+    /// its previous state first.
+    fn set_scene(&mut self, light: Light, scene: &Scene) -> Result<(), CommandError> {
+        self.send_command(&light.method("set_scene"), scene.params()?)?;
+        Ok(())
+    }
+
+    /// Starts a color flow after checking it against the protocol limits.
+    fn start_flow(&mut self, light: Light, flow: &Flow) -> Result<(), CommandError> {
+        flow.validate()?;
+        self.send_command(
+            &light.method("start_cf"),
+            vec![
+                Param::Uint32(flow.count()?),
+                Param::Uint8(flow.action as u8),
+                Param::Str(flow.expression()),
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Stores a name on the lamp. Names with non-ASCII characters are sent base64-encoded.
+    fn set_name(&mut self, name: &str) -> Result<(), CommandError> {
+        self.send_command("set_name", vec![Param::Str(encode_name(name))])?;
+        Ok(())
+    }
+
+    /// Saves the current state of the light as the state it is turned on in after a power
+    /// cut.
+    fn set_default(&mut self, light: Light) -> Result<(), CommandError> {
+        self.send_command(&light.method("set_default"), vec![])?;
+        Ok(())
+    }
+
+    /// Sets the power-off timer of the lamp, replacing any timer that is already set.
+    fn set_timer(&mut self, minutes: u32) -> Result<(), CommandError> {
+        self.send_command("cron_add", vec![Param::Uint8(0), Param::Uint32(minutes)])?;
+        Ok(())
+    }
+
+    /// Cancels the power-off timer of the lamp.
+    fn cancel_timer(&mut self) -> Result<(), CommandError> {
+        self.send_command("cron_del", vec![Param::Uint8(0)])?;
+        Ok(())
+    }
+
+    /// Stops a running color flow.
+    fn stop_flow(&mut self, light: Light) -> Result<(), CommandError> {
+        self.send_command(&light.method("stop_cf"), vec![])?;
+        Ok(())
+    }
+}
+
+/// Returns the `percentage, duration` parameters of the adjust methods.
+fn adjust_params(percentage: i8, transition: Transition) -> Vec<Param> {
+    vec![
+        Param::Int32(i32::from(percentage)),
+        Param::Uint32(transition.duration().as_millis() as u32),
+    ]
+}
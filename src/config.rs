@@ -0,0 +1,164 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    parse::{parse_duration, DurationParseError},
+    protocol::{Effect, Transition, TransitionError},
+};
+
+/// The environment variable that overrides the config file location.
+pub const CONFIG_ENV: &str = "YEELIGHT_CONFIG";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("unable to determine the config directory: set {CONFIG_ENV}, XDG_CONFIG_HOME or HOME")]
+    NoConfigDir,
+    #[error("unable to access {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("invalid config {path}: {source}")]
+    InvalidFormat {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    #[error("invalid transition for {name}: {message}")]
+    InvalidTransition { name: String, message: String },
+}
+
+/// A lamp known by name.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DeviceConfig {
+    pub host: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Default transition effect for this lamp.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub effect: Option<Effect>,
+    /// Default transition duration for this lamp, e.g. `2s` or `1500ms`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<String>,
+}
+
+impl DeviceConfig {
+    /// Returns the default transition duration, if one is configured.
+    pub fn duration(&self) -> Result<Option<std::time::Duration>, DurationParseError> {
+        self.duration.as_deref().map(parse_duration).transpose()
+    }
+
+    /// Returns the default transition of this lamp, filling in what isn't configured from
+    /// [`Transition::default`].
+    pub fn transition(&self, name: &str) -> Result<Transition, ConfigError> {
+        let invalid = |message: String| ConfigError::InvalidTransition {
+            name: name.to_string(),
+            message,
+        };
+        let default = Transition::default();
+        let duration = self.duration().map_err(|e| invalid(e.to_string()))?;
+        Transition::new(
+            self.effect.unwrap_or(default.effect()),
+            duration.unwrap_or(default.duration()),
+        )
+        .map_err(|e: TransitionError| invalid(e.to_string()))
+    }
+}
+
+/// The contents of the config file, stored as JSON:
+///
+/// ```json
+/// {
+///     "devices": {
+///         "bedroom": {"host": "192.168.1.10", "model": "ceiling4", "duration": "1s"}
+///     },
+///     "groups": {
+///         "upstairs": ["bedroom", "192.168.1.11"]
+///     }
+/// }
+/// ```
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+    #[serde(default)]
+    pub devices: BTreeMap<String, DeviceConfig>,
+    /// Lamps that are controlled together. Members are device names or hosts.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub groups: BTreeMap<String, Vec<String>>,
+}
+
+impl Config {
+    /// Returns the config file location: `$YEELIGHT_CONFIG`, or `yeelight/config.json` in
+    /// `$XDG_CONFIG_HOME` or `~/.config`.
+    pub fn path() -> Result<PathBuf, ConfigError> {
+        if let Some(path) = std::env::var_os(CONFIG_ENV) {
+            return Ok(PathBuf::from(path));
+        }
+        let dir = match std::env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => match std::env::var_os("HOME") {
+                Some(home) if !home.is_empty() => PathBuf::from(home).join(".config"),
+                _ => return Err(ConfigError::NoConfigDir),
+            },
+        };
+        Ok(dir.join("yeelight").join("config.json"))
+    }
+
+    /// Returns the members of the group `name`, or just `name` if there is no such group.
+    pub fn expand(&self, name: &str) -> Vec<String> {
+        match self.groups.get(name) {
+            Some(members) => members.clone(),
+            None => vec![name.to_string()],
+        }
+    }
+
+    /// Returns where the snapshot `name` is stored: `snapshots/NAME.json` next to the config
+    /// file.
+    pub fn snapshot_path(name: &str) -> Result<PathBuf, ConfigError> {
+        let path = Self::path()?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+        Ok(dir.join("snapshots").join(format!("{}.json", name)))
+    }
+
+    /// Loads the config file from [`Config::path`]. A missing file is an empty config.
+    pub fn load() -> Result<Self, ConfigError> {
+        Self::load_from(&Self::path()?)
+    }
+
+    pub fn load_from(path: &Path) -> Result<Self, ConfigError> {
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(source) => {
+                return Err(ConfigError::Io {
+                    path: path.to_path_buf(),
+                    source,
+                })
+            }
+        };
+        serde_json::from_slice(&data).map_err(|source| ConfigError::InvalidFormat {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    /// Writes the config to [`Config::path`], creating its directory if needed.
+    pub fn save(&self) -> Result<(), ConfigError> {
+        self.save_to(&Self::path()?)
+    }
+
+    pub fn save_to(&self, path: &Path) -> Result<(), ConfigError> {
+        let io_error = |source| ConfigError::Io {
+            path: path.to_path_buf(),
+            source,
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(io_error)?;
+        }
+        let mut data = serde_json::to_string_pretty(self).expect("config is serializable");
+        data.push('\n');
+        std::fs::write(path, data).map_err(io_error)
+    }
+}
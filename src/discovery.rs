@@ -0,0 +1,126 @@
+use std::{
+    collections::HashMap,
+    net::UdpSocket,
+    time::{Duration, Instant},
+};
+
+use crate::name::decode_name;
+
+const MULTICAST_ADDR: &str = "239.255.255.250:1982";
+
+const SEARCH_REQUEST: &str = "M-SEARCH * HTTP/1.1\r\n\
+    HOST: 239.255.255.250:1982\r\n\
+    MAN: \"ssdp:discover\"\r\n\
+    ST: wifi_bulb\r\n\
+    \r\n";
+
+/// A lamp that answered a discovery request.
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct Device {
+    pub host: String,
+    pub port: u16,
+    pub id: String,
+    pub model: String,
+    pub fw_ver: String,
+    pub support: Vec<String>,
+    pub power: String,
+    pub bright: String,
+    /// The name stored on the lamp, decoded with [`crate::decode_name`].
+    pub name: String,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DeviceParseError {
+    #[error("not a search response")]
+    NotAResponse,
+    #[error("missing header: {0}")]
+    MissingHeader(&'static str),
+    #[error("invalid location: {0}")]
+    InvalidLocation(String),
+}
+
+impl Device {
+    /// Parses a reply to the `M-SEARCH` request. Header names are matched case-insensitively.
+    pub fn parse(response: &str) -> Result<Self, DeviceParseError> {
+        let mut lines = response.lines();
+        match lines.next() {
+            Some(status) if status.trim() == "HTTP/1.1 200 OK" => {}
+            _ => return Err(DeviceParseError::NotAResponse),
+        }
+
+        let headers: HashMap<String, String> = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(k, v)| (k.trim().to_ascii_lowercase(), v.trim().to_string()))
+            .collect();
+        let header = |name: &'static str| {
+            headers
+                .get(name)
+                .cloned()
+                .ok_or(DeviceParseError::MissingHeader(name))
+        };
+
+        let location = header("location")?;
+        let (host, port) = location
+            .strip_prefix("yeelight://")
+            .and_then(|addr| addr.rsplit_once(':'))
+            .and_then(|(host, port)| Some((host.to_string(), port.parse().ok()?)))
+            .ok_or_else(|| DeviceParseError::InvalidLocation(location.clone()))?;
+
+        Ok(Device {
+            host,
+            port,
+            id: header("id")?,
+            model: header("model").unwrap_or_default(),
+            fw_ver: header("fw_ver").unwrap_or_default(),
+            support: header("support")
+                .unwrap_or_default()
+                .split_whitespace()
+                .map(String::from)
+                .collect(),
+            power: header("power").unwrap_or_default(),
+            bright: header("bright").unwrap_or_default(),
+            name: decode_name(&header("name").unwrap_or_default()),
+        })
+    }
+}
+
+/// Sends an SSDP `M-SEARCH` request to the Yeelight multicast group and collects the lamps
+/// that answer within `timeout`. Each lamp is reported once, in the order of the first reply.
+pub fn discover(timeout: Duration) -> std::io::Result<Vec<Device>> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    log::debug!("Sending search request to {}", MULTICAST_ADDR);
+    socket.send_to(SEARCH_REQUEST.as_bytes(), MULTICAST_ADDR)?;
+
+    let deadline = Instant::now() + timeout;
+    let mut devices: Vec<Device> = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+        socket.set_read_timeout(Some(remaining))?;
+        let (len, from) = match socket.recv_from(&mut buf) {
+            Ok(r) => r,
+            Err(ref e)
+                if e.kind() == std::io::ErrorKind::WouldBlock
+                    || e.kind() == std::io::ErrorKind::TimedOut =>
+            {
+                break;
+            }
+            Err(e) => return Err(e),
+        };
+
+        let response = String::from_utf8_lossy(&buf[..len]);
+        match Device::parse(&response) {
+            Ok(device) => {
+                log::debug!("Found {} at {}:{}", device.id, device.host, device.port);
+                if !devices.iter().any(|d| d.id == device.id) {
+                    devices.push(device);
+                }
+            }
+            Err(e) => log::debug!("Ignoring reply from {}: {}", from, e),
+        }
+    }
+    Ok(devices)
+}
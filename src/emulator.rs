@@ -0,0 +1,709 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{Arc, Mutex},
+};
+
+use serde_json::{json, Value};
+
+use crate::protocol::Light;
+
+/// The state of one light of an emulated lamp.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LightState {
+    pub power: bool,
+    pub bright: u8,
+    pub ct: u16,
+    pub rgb: u32,
+    pub hue: u16,
+    pub sat: u8,
+    /// 1 is RGB, 2 is color temperature, 3 is HSV.
+    pub color_mode: u8,
+    pub flowing: bool,
+}
+
+impl Default for LightState {
+    fn default() -> Self {
+        LightState {
+            power: false,
+            bright: 100,
+            ct: 4000,
+            rgb: 0xffffff,
+            hue: 0,
+            sat: 0,
+            color_mode: 2,
+            flowing: false,
+        }
+    }
+}
+
+/// The state of an emulated lamp with a main and an ambient light.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LampState {
+    pub main: LightState,
+    pub ambient: LightState,
+    /// 0 is daylight, 1 is moonlight.
+    pub active_mode: u8,
+    pub nl_br: u8,
+    pub name: String,
+    pub music_on: bool,
+    /// Minutes left on the power-off timer, 0 if none is set. The emulator doesn't count it
+    /// down.
+    pub delayoff: u32,
+}
+
+impl Default for LampState {
+    fn default() -> Self {
+        LampState {
+            main: LightState::default(),
+            ambient: LightState::default(),
+            active_mode: 0,
+            nl_br: 1,
+            name: String::new(),
+            music_on: false,
+            delayoff: 0,
+        }
+    }
+}
+
+impl LampState {
+    fn light_mut(&mut self, light: Light) -> &mut LightState {
+        match light {
+            Light::Main => &mut self.main,
+            Light::Ambient => &mut self.ambient,
+        }
+    }
+
+    /// Returns every property the lamp reports, with values formatted the way `get_prop`
+    /// returns them.
+    pub fn props(&self) -> Vec<(&'static str, String)> {
+        let power = |on: bool| String::from(if on { "on" } else { "off" });
+        let flag = |on: bool| String::from(if on { "1" } else { "0" });
+        vec![
+            ("power", power(self.main.power)),
+            ("bright", self.main.bright.to_string()),
+            ("ct", self.main.ct.to_string()),
+            ("rgb", self.main.rgb.to_string()),
+            ("hue", self.main.hue.to_string()),
+            ("sat", self.main.sat.to_string()),
+            ("color_mode", self.main.color_mode.to_string()),
+            ("flowing", flag(self.main.flowing)),
+            ("delayoff", self.delayoff.to_string()),
+            ("music_on", flag(self.music_on)),
+            ("name", self.name.clone()),
+            ("bg_power", power(self.ambient.power)),
+            ("bg_flowing", flag(self.ambient.flowing)),
+            ("bg_ct", self.ambient.ct.to_string()),
+            ("bg_lmode", self.ambient.color_mode.to_string()),
+            ("bg_bright", self.ambient.bright.to_string()),
+            ("bg_rgb", self.ambient.rgb.to_string()),
+            ("bg_hue", self.ambient.hue.to_string()),
+            ("bg_sat", self.ambient.sat.to_string()),
+            ("nl_br", self.nl_br.to_string()),
+            ("active_mode", self.active_mode.to_string()),
+        ]
+    }
+
+    /// Changes `prop` by `percentage` of its range. A percentage of 0 steps up by a tenth and
+    /// wraps around, the way `set_adjust` with `circle` does. The color always steps to the
+    /// next hue.
+    fn adjust(&mut self, light: Light, prop: &str, percentage: i64) {
+        let step = |value: i64, min: i64, max: i64| {
+            if percentage == 0 {
+                let next = value + (max - min) / 10;
+                if next > max {
+                    min
+                } else {
+                    next
+                }
+            } else {
+                (value + (max - min) * percentage / 100).clamp(min, max)
+            }
+        };
+        let moonlight = light == Light::Main && self.active_mode == 1;
+        match prop {
+            "bright" if moonlight => self.nl_br = step(self.nl_br.into(), 1, 100) as u8,
+            "bright" => {
+                let light = self.light_mut(light);
+                light.bright = step(light.bright.into(), 1, 100) as u8;
+            }
+            "ct" => {
+                let light = self.light_mut(light);
+                light.ct = step(light.ct.into(), 1700, 6500) as u16;
+                light.color_mode = 2;
+            }
+            _ => {
+                let light = self.light_mut(light);
+                light.hue = (light.hue + 30) % 360;
+                light.color_mode = 3;
+            }
+        }
+    }
+
+    fn prop(&self, name: &str) -> String {
+        self.props()
+            .into_iter()
+            .find(|(k, _)| *k == name)
+            .map(|(_, v)| v)
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+enum MethodError {
+    #[error("unsupported method")]
+    UnsupportedMethod,
+    #[error("invalid params")]
+    InvalidParams,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct Request {
+    id: u16,
+    method: String,
+    #[serde(default)]
+    params: Vec<Value>,
+}
+
+fn int_param(
+    params: &[Value],
+    index: usize,
+    range: std::ops::RangeInclusive<i64>,
+) -> Result<i64, MethodError> {
+    params
+        .get(index)
+        .and_then(Value::as_i64)
+        .filter(|v| range.contains(v))
+        .ok_or(MethodError::InvalidParams)
+}
+
+fn str_param(params: &[Value], index: usize) -> Result<&str, MethodError> {
+    params
+        .get(index)
+        .and_then(Value::as_str)
+        .ok_or(MethodError::InvalidParams)
+}
+
+fn execute(
+    state: &mut LampState,
+    method: &str,
+    params: &[Value],
+) -> Result<Vec<Value>, MethodError> {
+    let (light, method) = match method.strip_prefix("bg_") {
+        Some(method) => (Light::Ambient, method),
+        None => (Light::Main, method),
+    };
+    match (light, method) {
+        (Light::Main, "get_prop") => {
+            return Ok(params
+                .iter()
+                .map(|p| Value::from(p.as_str().map(|name| state.prop(name)).unwrap_or_default()))
+                .collect());
+        }
+        (Light::Main, "set_music") => match int_param(params, 0, 0..=1)? {
+            1 => {
+                str_param(params, 1)?;
+                int_param(params, 2, 1..=65535)?;
+                state.music_on = true;
+            }
+            _ => state.music_on = false,
+        },
+        (Light::Main, "set_name") => state.name = str_param(params, 0)?.to_string(),
+        (Light::Main, "dev_toggle") => {
+            let on = !state.main.power;
+            if !on {
+                state.delayoff = 0;
+            }
+            state.main.power = on;
+            state.ambient.power = on;
+        }
+        (Light::Main, "cron_add") => {
+            int_param(params, 0, 0..=0)?;
+            state.delayoff = int_param(params, 1, 1..=u32::MAX as i64)? as u32;
+        }
+        (Light::Main, "cron_get") => {
+            int_param(params, 0, 0..=0)?;
+            return Ok(if state.delayoff > 0 {
+                vec![json!({"type": 0, "delay": state.delayoff, "mix": 0})]
+            } else {
+                vec![]
+            });
+        }
+        (Light::Main, "cron_del") => {
+            int_param(params, 0, 0..=0)?;
+            state.delayoff = 0;
+        }
+        (_, "set_power") => {
+            let on = match str_param(params, 0)? {
+                "on" => true,
+                "off" => false,
+                _ => return Err(MethodError::InvalidParams),
+            };
+            if light == Light::Main && on {
+                match params.get(3).and_then(Value::as_i64).unwrap_or(0) {
+                    5 => state.active_mode = 1,
+                    1 => {
+                        state.active_mode = 0;
+                        state.main.color_mode = 2;
+                    }
+                    2 => state.main.color_mode = 1,
+                    3 => state.main.color_mode = 3,
+                    _ => {}
+                }
+            }
+            if light == Light::Main && !on {
+                state.delayoff = 0;
+            }
+            state.light_mut(light).power = on;
+        }
+        // The emulator can't lose power, so there is nothing to restore a saved state on.
+        (_, "set_default") => {}
+        (_, "toggle") => {
+            let on = !state.light_mut(light).power;
+            if light == Light::Main && !on {
+                state.delayoff = 0;
+            }
+            state.light_mut(light).power = on;
+        }
+        (_, "set_bright") => {
+            let bright = int_param(params, 0, 1..=100)? as u8;
+            if light == Light::Main && state.active_mode == 1 {
+                state.nl_br = bright;
+            } else {
+                state.light_mut(light).bright = bright;
+            }
+        }
+        (_, "set_ct_abx") => {
+            let light = state.light_mut(light);
+            light.ct = int_param(params, 0, 1700..=6500)? as u16;
+            light.color_mode = 2;
+        }
+        (_, "set_rgb") => {
+            let light = state.light_mut(light);
+            light.rgb = int_param(params, 0, 0..=0xffffff)? as u32;
+            light.color_mode = 1;
+        }
+        (_, "set_hsv") => {
+            let hue = int_param(params, 0, 0..=359)? as u16;
+            let sat = int_param(params, 1, 0..=100)? as u8;
+            let light = state.light_mut(light);
+            light.hue = hue;
+            light.sat = sat;
+            light.color_mode = 3;
+        }
+        (_, "start_cf") => {
+            int_param(params, 0, 0..=u32::MAX as i64)?;
+            int_param(params, 1, 0..=2)?;
+            let expression = str_param(params, 2)?;
+            if expression.split(',').count() % 4 != 0 {
+                return Err(MethodError::InvalidParams);
+            }
+            state.light_mut(light).flowing = true;
+        }
+        (_, "stop_cf") => state.light_mut(light).flowing = false,
+        (_, "adjust_bright" | "adjust_ct" | "adjust_color") => {
+            let percentage = int_param(params, 0, -100..=100)?;
+            int_param(params, 1, 30..=u32::MAX as i64)?;
+            match method {
+                "adjust_bright" => state.adjust(light, "bright", percentage),
+                "adjust_ct" => state.adjust(light, "ct", percentage),
+                _ => state.adjust(light, "color", percentage),
+            }
+        }
+        (_, "set_adjust") => {
+            let prop = str_param(params, 1)?;
+            let percentage = match (str_param(params, 0)?, prop) {
+                ("increase", "bright" | "ct") => 10,
+                ("decrease", "bright" | "ct") => -10,
+                ("circle", "bright" | "ct" | "color") => 0,
+                _ => return Err(MethodError::InvalidParams),
+            };
+            state.adjust(light, prop, percentage);
+        }
+        (_, "set_scene") => {
+            let kind = str_param(params, 0)?;
+            let bright = match kind {
+                "color" | "ct" => Some(int_param(params, 2, 1..=100)? as u8),
+                "hsv" => Some(int_param(params, 3, 1..=100)? as u8),
+                "auto_delay_off" => Some(int_param(params, 1, 1..=100)? as u8),
+                _ => None,
+            };
+            match kind {
+                "color" => {
+                    let rgb = int_param(params, 1, 0..=0xffffff)? as u32;
+                    let light = state.light_mut(light);
+                    light.rgb = rgb;
+                    light.color_mode = 1;
+                }
+                "hsv" => {
+                    let hue = int_param(params, 1, 0..=359)? as u16;
+                    let sat = int_param(params, 2, 0..=100)? as u8;
+                    let light = state.light_mut(light);
+                    light.hue = hue;
+                    light.sat = sat;
+                    light.color_mode = 3;
+                }
+                "ct" => {
+                    let ct = int_param(params, 1, 1700..=6500)? as u16;
+                    let light = state.light_mut(light);
+                    light.ct = ct;
+                    light.color_mode = 2;
+                }
+                "cf" => {
+                    int_param(params, 1, 0..=u32::MAX as i64)?;
+                    int_param(params, 2, 0..=2)?;
+                    if str_param(params, 3)?.split(',').count() % 4 != 0 {
+                        return Err(MethodError::InvalidParams);
+                    }
+                    state.light_mut(light).flowing = true;
+                }
+                "auto_delay_off" => {
+                    state.delayoff = int_param(params, 2, 1..=u32::MAX as i64)? as u32;
+                }
+                _ => return Err(MethodError::InvalidParams),
+            }
+            if light == Light::Main {
+                state.active_mode = 0;
+            }
+            let light = state.light_mut(light);
+            if let Some(bright) = bright {
+                light.bright = bright;
+            }
+            light.power = true;
+        }
+        _ => return Err(MethodError::UnsupportedMethod),
+    }
+    Ok(vec![json!("ok")])
+}
+
+/// A misbehaviour the emulator applies to a single command.
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(tag = "fault", rename_all = "snake_case")]
+pub enum Fault {
+    /// Reply normally.
+    #[default]
+    None,
+    /// Execute the command but wait before replying.
+    Delay { ms: u64 },
+    /// Execute the command but never reply.
+    Drop,
+    /// Close the connection without executing the command.
+    Disconnect,
+    /// Reject the command the way a lamp does when a client sends too many commands.
+    QuotaExceeded,
+}
+
+/// A script of faults for the emulator, usually loaded from a JSON file:
+///
+/// ```json
+/// {
+///     "refuse_connections": 2,
+///     "commands": [
+///         {"fault": "delay", "ms": 300},
+///         {"fault": "none"},
+///         {"fault": "drop"},
+///         {"fault": "quota_exceeded"},
+///         {"fault": "disconnect"}
+///     ]
+/// }
+/// ```
+///
+/// The first `refuse_connections` connections are closed as soon as they are accepted, the way a
+/// lamp drops connections beyond its limit. The count restarts with every [`Emulator::set_scenario`].
+/// `commands` is applied in order to the commands received over all connections; once it is
+/// exhausted, every command is answered normally.
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+pub struct Scenario {
+    #[serde(default)]
+    pub refuse_connections: u32,
+    #[serde(default)]
+    pub commands: Vec<Fault>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ScenarioError {
+    #[error("unable to read scenario: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid scenario: {0}")]
+    InvalidFormat(#[from] serde_json::Error),
+}
+
+impl Scenario {
+    pub fn load(path: impl AsRef<std::path::Path>) -> Result<Self, ScenarioError> {
+        let data = std::fs::read(path)?;
+        Ok(serde_json::from_slice(&data)?)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    state: LampState,
+    clients: Vec<(usize, TcpStream)>,
+    next_client: usize,
+    scenario: Scenario,
+    refused: u32,
+    commands: usize,
+    received: Vec<(String, Vec<Value>)>,
+}
+
+impl Shared {
+    fn broadcast(&mut self, line: &str) {
+        self.clients.retain_mut(|(id, stream)| {
+            match stream.write_all(format!("{}\r\n", line).as_bytes()) {
+                Ok(_) => true,
+                Err(e) => {
+                    log::debug!("Dropping client {}: {}", id, e);
+                    false
+                }
+            }
+        });
+    }
+
+    fn next_fault(&mut self) -> Fault {
+        let fault = self
+            .scenario
+            .commands
+            .get(self.commands)
+            .copied()
+            .unwrap_or_default();
+        self.commands += 1;
+        fault
+    }
+}
+
+/// A fake lamp that speaks the Yeelight LAN protocol over TCP.
+///
+/// Every connected client gets a reply to its own commands and a `props` notification for
+/// each state change, just like a real lamp. A [`Scenario`] can make it misbehave.
+#[derive(Debug)]
+pub struct Emulator {
+    listener: TcpListener,
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl Emulator {
+    /// Listens on `addr`. Use port 0 to let the OS pick a free port.
+    pub fn bind(addr: impl ToSocketAddrs) -> std::io::Result<Self> {
+        Ok(Emulator {
+            listener: TcpListener::bind(addr)?,
+            shared: Arc::default(),
+        })
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Returns a copy of the current lamp state.
+    pub fn state(&self) -> LampState {
+        self.shared.lock().unwrap().state.clone()
+    }
+
+    /// Returns the method and parameters of every command received so far, including the ones
+    /// sent in music mode.
+    pub fn received(&self) -> Vec<(String, Vec<Value>)> {
+        self.shared.lock().unwrap().received.clone()
+    }
+
+    /// Replaces the lamp state without notifying clients.
+    pub fn set_state(&self, state: LampState) {
+        self.shared.lock().unwrap().state = state;
+    }
+
+    /// Replaces the fault scenario and restarts it from the beginning.
+    pub fn set_scenario(&self, scenario: Scenario) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.scenario = scenario;
+        shared.refused = 0;
+        shared.commands = 0;
+    }
+
+    /// Accepts connections forever, serving each one on its own thread.
+    pub fn run(&self) -> std::io::Result<()> {
+        for stream in self.listener.incoming() {
+            let stream = stream?;
+            {
+                let mut shared = self.shared.lock().unwrap();
+                if shared.refused < shared.scenario.refuse_connections {
+                    shared.refused += 1;
+                    log::debug!(
+                        "Refusing connection {} of {}",
+                        shared.refused,
+                        shared.scenario.refuse_connections
+                    );
+                    let _ = stream.shutdown(std::net::Shutdown::Both);
+                    continue;
+                }
+            }
+            let shared = Arc::clone(&self.shared);
+            std::thread::spawn(move || {
+                let peer = stream.peer_addr().ok();
+                if let Err(e) = serve(shared, stream) {
+                    log::debug!("Connection from {:?} failed: {}", peer, e);
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+fn serve(shared: Arc<Mutex<Shared>>, stream: TcpStream) -> std::io::Result<()> {
+    stream.set_nodelay(true)?;
+    let client_id = {
+        let mut shared = shared.lock().unwrap();
+        let id = shared.next_client;
+        shared.next_client += 1;
+        shared.clients.push((id, stream.try_clone()?));
+        id
+    };
+    log::debug!(
+        "Client {} connected from {}",
+        client_id,
+        stream.peer_addr()?
+    );
+
+    let result = serve_commands(&shared, client_id, stream, false);
+
+    shared
+        .lock()
+        .unwrap()
+        .clients
+        .retain(|(id, _)| *id != client_id);
+    log::debug!("Client {} disconnected", client_id);
+    result
+}
+
+/// Connects back to a music mode server. Commands received over this connection are executed
+/// without replies or faults.
+fn serve_music(shared: Arc<Mutex<Shared>>, host: &str, port: u16) -> std::io::Result<()> {
+    let stream = TcpStream::connect((host, port))?;
+    stream.set_nodelay(true)?;
+    let client_id = {
+        let mut shared = shared.lock().unwrap();
+        shared.next_client += 1;
+        shared.next_client - 1
+    };
+    log::debug!(
+        "Client {} is a music mode connection to {}:{}",
+        client_id,
+        host,
+        port
+    );
+    let result = serve_commands(&shared, client_id, stream, true);
+    log::debug!("Client {} disconnected", client_id);
+    result
+}
+
+fn serve_commands(
+    shared: &Arc<Mutex<Shared>>,
+    client_id: usize,
+    stream: TcpStream,
+    music: bool,
+) -> std::io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        log::debug!("Client {} sent: {}", client_id, line);
+
+        let request: Request = match serde_json::from_str(line) {
+            Ok(request) => request,
+            Err(e) => {
+                log::debug!("Ignoring invalid request: {}", e);
+                continue;
+            }
+        };
+        shared
+            .lock()
+            .unwrap()
+            .received
+            .push((request.method.clone(), request.params.clone()));
+
+        let fault = if music {
+            Fault::Drop
+        } else {
+            shared.lock().unwrap().next_fault()
+        };
+        if fault != Fault::None && !music {
+            log::debug!("Injecting {:?} for request {}", fault, request.id);
+        }
+        match fault {
+            Fault::Disconnect => {
+                writer.shutdown(std::net::Shutdown::Both)?;
+                return Ok(());
+            }
+            Fault::QuotaExceeded => {
+                let reply = json!({
+                    "id": request.id,
+                    "error": {"code": -1, "message": "client quota exceeded"},
+                });
+                writer.write_all(format!("{}\r\n", reply).as_bytes())?;
+                continue;
+            }
+            _ => {}
+        }
+
+        let (reply, changed) = {
+            let mut shared = shared.lock().unwrap();
+            let before = shared.state.props();
+            let reply = match execute(&mut shared.state, &request.method, &request.params) {
+                Ok(result) => json!({"id": request.id, "result": result}),
+                Err(e) => {
+                    json!({"id": request.id, "error": {"code": -1, "message": e.to_string()}})
+                }
+            };
+            let changed: serde_json::Map<String, Value> = shared
+                .state
+                .props()
+                .into_iter()
+                .zip(before)
+                .filter(|(after, before)| after != before)
+                .map(|((k, v), _)| (k.to_string(), prop_value(k, v)))
+                .collect();
+            (reply, changed)
+        };
+
+        match fault {
+            Fault::Drop => {}
+            Fault::Delay { ms } => {
+                std::thread::sleep(std::time::Duration::from_millis(ms));
+                writer.write_all(format!("{}\r\n", reply).as_bytes())?;
+            }
+            _ => writer.write_all(format!("{}\r\n", reply).as_bytes())?,
+        }
+
+        if request.method == "set_music"
+            && reply.get("result").is_some()
+            && request.params.first() == Some(&json!(1))
+        {
+            let shared = Arc::clone(shared);
+            let host = request.params[1].as_str().unwrap_or_default().to_string();
+            let port = request.params[2].as_u64().unwrap_or_default() as u16;
+            std::thread::spawn(move || {
+                if let Err(e) = serve_music(shared, &host, port) {
+                    log::debug!("Music mode connection to {}:{} failed: {}", host, port, e);
+                }
+            });
+        }
+
+        if !changed.is_empty() {
+            let notification = json!({"method": "props", "params": changed});
+            shared.lock().unwrap().broadcast(&notification.to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Notifications carry numeric properties as numbers, unlike `get_prop` replies.
+fn prop_value(name: &str, value: String) -> Value {
+    match name {
+        "power" | "bg_power" | "name" => Value::from(value),
+        _ => value
+            .parse::<u64>()
+            .map(Value::from)
+            .unwrap_or(Value::from(value)),
+    }
+}
@@ -0,0 +1,214 @@
+use std::time::Duration;
+
+use crate::{
+    parse::{parse_duration, parse_hex_color, DurationParseError},
+    protocol::CT_RANGE,
+};
+
+/// The shortest transition a flow step may have.
+pub const MIN_STEP_DURATION: Duration = Duration::from_millis(50);
+
+/// A single state change of a color flow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowStep {
+    /// Change to an RGB color. `bright` keeps the current brightness when `None`.
+    Rgb {
+        duration: Duration,
+        rgb: u32,
+        bright: Option<u8>,
+    },
+    /// Change to a color temperature in Kelvin.
+    Ct {
+        duration: Duration,
+        ct: u16,
+        bright: Option<u8>,
+    },
+    /// Keep the current state.
+    Sleep { duration: Duration },
+}
+
+/// What the lamp does once a flow has finished.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FlowAction {
+    /// Return to the state before the flow started.
+    #[default]
+    Recover = 0,
+    /// Stay at the last state of the flow.
+    Stay = 1,
+    /// Turn off.
+    Off = 2,
+}
+
+/// A color flow for `start_cf`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Flow {
+    /// How many times the steps are played, 0 means forever.
+    pub repeat: u32,
+    pub action: FlowAction,
+    pub steps: Vec<FlowStep>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum FlowError {
+    #[error("flow has no steps")]
+    Empty,
+    #[error("step {0}: duration should be at least 50ms")]
+    DurationTooShort(usize),
+    #[error("step {0}: duration is too long")]
+    DurationTooLong(usize),
+    #[error("step {0}: color should be between 0 and 0xffffff")]
+    InvalidColor(usize),
+    #[error("step {0}: color temperature should be between 1700 and 6500")]
+    InvalidColorTemperature(usize),
+    #[error("step {0}: brightness should be between 1 and 100")]
+    InvalidBrightness(usize),
+    #[error("too many repeats")]
+    TooManyRepeats,
+}
+
+impl FlowStep {
+    fn duration(&self) -> Duration {
+        match *self {
+            FlowStep::Rgb { duration, .. }
+            | FlowStep::Ct { duration, .. }
+            | FlowStep::Sleep { duration } => duration,
+        }
+    }
+
+    fn validate(&self, index: usize) -> Result<(), FlowError> {
+        if self.duration() < MIN_STEP_DURATION {
+            return Err(FlowError::DurationTooShort(index));
+        }
+        if self.duration().as_millis() > u32::MAX as u128 {
+            return Err(FlowError::DurationTooLong(index));
+        }
+        let bright = match *self {
+            FlowStep::Rgb { rgb, bright, .. } => {
+                if rgb > 0xffffff {
+                    return Err(FlowError::InvalidColor(index));
+                }
+                bright
+            }
+            FlowStep::Ct { ct, bright, .. } => {
+                if !CT_RANGE.contains(&ct) {
+                    return Err(FlowError::InvalidColorTemperature(index));
+                }
+                bright
+            }
+            FlowStep::Sleep { .. } => None,
+        };
+        if let Some(bright) = bright {
+            if !(1..=100).contains(&bright) {
+                return Err(FlowError::InvalidBrightness(index));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the `duration,mode,value,brightness` tuple of the flow expression.
+    fn expression(&self) -> String {
+        let bright = |bright: Option<u8>| bright.map_or(-1, i16::from);
+        match *self {
+            FlowStep::Rgb {
+                duration,
+                rgb,
+                bright: b,
+            } => format!("{},1,{},{}", duration.as_millis(), rgb, bright(b)),
+            FlowStep::Ct {
+                duration,
+                ct,
+                bright: b,
+            } => format!("{},2,{},{}", duration.as_millis(), ct, bright(b)),
+            FlowStep::Sleep { duration } => format!("{},7,0,0", duration.as_millis()),
+        }
+    }
+}
+
+impl Flow {
+    /// Checks the flow against the protocol limits.
+    pub fn validate(&self) -> Result<(), FlowError> {
+        if self.steps.is_empty() {
+            return Err(FlowError::Empty);
+        }
+        for (i, step) in self.steps.iter().enumerate() {
+            step.validate(i + 1)?;
+        }
+        self.count()?;
+        Ok(())
+    }
+
+    /// Returns the total number of state changes, which is what `start_cf` expects.
+    pub fn count(&self) -> Result<u32, FlowError> {
+        u32::try_from(self.steps.len())
+            .ok()
+            .and_then(|len| self.repeat.checked_mul(len))
+            .ok_or(FlowError::TooManyRepeats)
+    }
+
+    /// Returns the flow expression for `start_cf`.
+    pub fn expression(&self) -> String {
+        self.steps
+            .iter()
+            .map(FlowStep::expression)
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum FlowParseError {
+    #[error(
+        "invalid format: expected rgb:RRGGBB[@B]/DURATION, ct:K[@B]/DURATION or sleep:DURATION"
+    )]
+    InvalidFormat,
+    #[error("invalid number: {0}")]
+    InvalidNumber(#[from] std::num::ParseIntError),
+    #[error("invalid color: {0}")]
+    InvalidColor(String),
+    #[error("invalid duration: {0}")]
+    InvalidDuration(#[from] DurationParseError),
+    #[error("invalid end action: expected recover, stay or off")]
+    InvalidAction,
+}
+
+/// Parses a flow step such as `rgb:#ff0000@80/500ms`, `ct:2700/2s` or `sleep:1s`.
+pub fn parse_flow_step(input: &str) -> Result<FlowStep, FlowParseError> {
+    let (kind, rest) = input.split_once(':').ok_or(FlowParseError::InvalidFormat)?;
+    if kind == "sleep" {
+        return Ok(FlowStep::Sleep {
+            duration: parse_duration(rest)?,
+        });
+    }
+
+    let (value, duration) = rest.split_once('/').ok_or(FlowParseError::InvalidFormat)?;
+    let duration = parse_duration(duration)?;
+    let (value, bright) = match value.split_once('@') {
+        Some((value, bright)) => (value, Some(bright.parse()?)),
+        None => (value, None),
+    };
+
+    match kind {
+        "rgb" => Ok(FlowStep::Rgb {
+            duration,
+            rgb: parse_hex_color(value)
+                .ok_or_else(|| FlowParseError::InvalidColor(value.to_string()))?,
+            bright,
+        }),
+        "ct" => Ok(FlowStep::Ct {
+            duration,
+            ct: value.trim_end_matches('K').parse()?,
+            bright,
+        }),
+        _ => Err(FlowParseError::InvalidFormat),
+    }
+}
+
+/// Parses the end action of a flow: `recover`, `stay` or `off`.
+pub fn parse_flow_action(input: &str) -> Result<FlowAction, FlowParseError> {
+    match input {
+        "recover" => Ok(FlowAction::Recover),
+        "stay" => Ok(FlowAction::Stay),
+        "off" => Ok(FlowAction::Off),
+        _ => Err(FlowParseError::InvalidAction),
+    }
+}
@@ -0,0 +1,52 @@
+//! Client library for controlling Yeelight lamps over the LAN protocol.
+//!
+//! ```no_run
+//! use yeelight::{Client, Commands, Light, Mode, Power, Transition};
+//!
+//! let mut client = Client::connect("192.168.1.10", 55443)?;
+//! let transition = Transition::default();
+//! client.set_power(Light::Main, Power::On, Some(Mode::Normal), transition)?;
+//! client.set_bright(Light::Main, 80, transition)?;
+//! # Ok::<(), yeelight::CommandError>(())
+//! ```
+
+mod client;
+mod commands;
+mod config;
+mod discovery;
+pub mod emulator;
+mod flow;
+mod music;
+mod name;
+mod parse;
+mod props;
+mod protocol;
+mod ratelimit;
+mod scene;
+mod snapshot;
+
+pub use crate::{
+    client::{Client, ClientConfig, CommandError},
+    commands::Commands,
+    config::{Config, ConfigError, DeviceConfig, CONFIG_ENV},
+    discovery::{discover, Device, DeviceParseError},
+    flow::{
+        parse_flow_action, parse_flow_step, Flow, FlowAction, FlowError, FlowParseError, FlowStep,
+        MIN_STEP_DURATION,
+    },
+    music::MusicSession,
+    name::{decode_name, encode_name},
+    parse::{
+        parse_adjustment, parse_ambient, parse_duration, parse_hsv, parse_main, Adjustment,
+        AdjustmentParseError, AmbientParseError, AmbientSetting, DurationParseError, HsvParseError,
+        MainParseError, MainSetting, NAMED_COLORS,
+    },
+    props::{ColorMode, Properties, PropertyError, PROPERTY_NAMES},
+    protocol::{
+        AdjustAction, AdjustProp, DeviceError, Effect, Light, Message, Mode, Notification, Param,
+        Power, Response, Transition, TransitionError, CT_RANGE,
+    },
+    ratelimit::{RateLimit, RateLimitError, RateLimitPolicy},
+    scene::Scene,
+    snapshot::{LightSnapshot, Snapshot, SnapshotError},
+};
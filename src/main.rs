@@ -1,284 +1,810 @@
-use std::{
-    io::{BufRead, Write},
-    net::ToSocketAddrs,
+use yeelight::{
+    discover, parse_ambient, parse_duration, parse_flow_step, parse_main, Adjustment,
+    AmbientSetting, Client, ClientConfig, CommandError, Commands, Config, ConfigError,
+    DeviceConfig, Effect, Flow, FlowAction, Light, MainSetting, Mode, Power, Properties, RateLimit,
+    RateLimitPolicy, Scene, Snapshot, Transition,
 };
 
-#[derive(Debug, thiserror::Error)]
-enum MainParseError {
-    #[error("invalid format: expected X or moonlight:V or normal:V or off")]
-    InvalidFormat,
-    #[error("invalid number: {0}")]
-    InvalidNumber(#[from] std::num::ParseIntError),
-    #[error("invalid value: should be between 0 and 100")]
-    InvalidValue,
+fn transition(
+    matches: &clap::ArgMatches,
+    light: &str,
+    device: Option<(&str, &DeviceConfig)>,
+) -> Result<Transition, Box<dyn std::error::Error>> {
+    let effect = matches
+        .get_one::<String>(&format!("{}-effect", light))
+        .or(matches.get_one::<String>("effect"))
+        .map(String::as_str);
+    let duration = matches
+        .get_one::<std::time::Duration>(&format!("{}-duration", light))
+        .or(matches.get_one::<std::time::Duration>("duration"))
+        .copied();
+
+    let default = match device {
+        Some((name, device)) => device.transition(name)?,
+        None => Transition::default(),
+    };
+    let effect = match effect {
+        Some("sudden") => Effect::Sudden,
+        Some(_) => Effect::Smooth,
+        None => default.effect(),
+    };
+    Ok(Transition::new(
+        effect,
+        duration.unwrap_or(default.duration()),
+    )?)
 }
 
-#[derive(Debug)]
-enum Mode {
-    Normal = 1,
-    Moonlight = 5,
+/// A lamp to apply the settings to.
+struct Target {
+    /// The name the lamp was given on the command line or in a group.
+    name: String,
+    host: String,
+    config: ClientConfig,
+    main_transition: Transition,
+    ambient_transition: Transition,
 }
 
-fn parse_main(input: &str) -> Result<(Mode, u8), MainParseError> {
-    if input == "off" {
-        return Ok((Mode::Normal, 0));
+/// Returns the current values of the properties `names` if `light` should be set with a single
+/// scene, which turns it on straight into the new state instead of showing the old one first.
+/// Scenes have no transition, so with a smooth one they are only used while the light is off.
+fn scene_props(
+    client: &mut Client,
+    light: Light,
+    names: &[&str],
+    transition: Transition,
+) -> Result<Option<Properties>, CommandError> {
+    let smooth = transition.effect() == Effect::Smooth;
+    let power = light.method("power");
+    let names: Vec<&str> = smooth
+        .then_some(power.as_str())
+        .into_iter()
+        .chain(names.iter().copied())
+        .collect();
+    if names.is_empty() {
+        return Ok(Some(Properties::default()));
+    }
+    let current = Properties::from_values(&names, &client.get_prop(&names)?)?;
+    let power = match light {
+        Light::Main => current.power,
+        Light::Ambient => current.bg_power,
+    };
+    if smooth && power != Some(Power::Off) {
+        return Ok(None);
     }
+    Ok(Some(current))
+}
 
-    if let Ok(v) = input.parse::<u8>() {
-        match v {
-            0..=100 => return Ok((Mode::Moonlight, v)),
-            0..=200 => return Ok((Mode::Normal, v - 100)),
-            _ => return Err(MainParseError::InvalidFormat),
-        }
+/// Lamps without an ambient light report `bg_power` as an empty string.
+fn has_ambient(client: &mut Client) -> Result<bool, CommandError> {
+    let power = client.get_prop(&["bg_power"])?;
+    Ok(power.first().is_some_and(|power| !power.is_empty()))
+}
+
+fn adjust(
+    client: &mut Client,
+    light: Light,
+    adjustment: Adjustment,
+    transition: Transition,
+) -> Result<(), CommandError> {
+    match adjustment {
+        Adjustment::Bright(percentage) => client.adjust_bright(light, percentage, transition),
+        Adjustment::Ct(percentage) => client.adjust_ct(light, percentage, transition),
     }
+}
+
+/// Sends the commands for the requested settings and returns the lights that were toggled.
+fn apply(
+    client: &mut Client,
+    toggle_device: bool,
+    main: Option<MainSetting>,
+    ambient: Option<AmbientSetting>,
+    main_transition: Transition,
+    ambient_transition: Transition,
+) -> Result<Vec<Light>, CommandError> {
+    let mut toggled = Vec::new();
 
-    let parts: Vec<&str> = input.split(':').collect();
-    if parts.len() != 2 {
-        return Err(MainParseError::InvalidFormat);
+    if toggle_device {
+        client.toggle_device()?;
+        toggled.extend([Light::Main, Light::Ambient]);
     }
 
-    let v: u8 = parts[1].parse().map_err(MainParseError::InvalidNumber)?;
-    if v > 100 {
-        return Err(MainParseError::InvalidValue);
+    if let Some(main) = main {
+        match main {
+            MainSetting::Off => client.set_power(Light::Main, Power::Off, None, main_transition)?,
+            MainSetting::Toggle => {
+                client.toggle(Light::Main)?;
+                toggled.push(Light::Main);
+            }
+            MainSetting::Adjust(adjustment) => {
+                adjust(client, Light::Main, adjustment, main_transition)?
+            }
+            MainSetting::On { mode, bright, ct } => {
+                let scene = match mode {
+                    Mode::Normal => {
+                        let missing: Vec<&str> = [
+                            bright.is_none().then_some("bright"),
+                            ct.is_none().then_some("ct"),
+                        ]
+                        .into_iter()
+                        .flatten()
+                        .collect();
+                        scene_props(client, Light::Main, &missing, main_transition)?.and_then(
+                            |current| {
+                                Some(Scene::Ct {
+                                    ct: ct.or(current.ct)?,
+                                    bright: bright.or(current.bright)?,
+                                })
+                            },
+                        )
+                    }
+                    Mode::Moonlight => None,
+                };
+                match scene {
+                    Some(scene) => client.set_scene(Light::Main, &scene)?,
+                    None => {
+                        client.set_power(Light::Main, Power::On, Some(mode), main_transition)?;
+                        if let Some(ct) = ct {
+                            client.set_ct(Light::Main, ct, main_transition)?;
+                        }
+                        if let Some(bright) = bright {
+                            client.set_bright(Light::Main, bright, main_transition)?;
+                        }
+                    }
+                }
+            }
+        }
     }
-    match parts[0] {
-        "moonlight" => Ok((Mode::Moonlight, v)),
-        "normal" => Ok((Mode::Normal, v)),
-        _ => Err(MainParseError::InvalidValue),
+
+    if let Some(ambient) = ambient {
+        match ambient {
+            AmbientSetting::Off => {
+                client.set_power(Light::Ambient, Power::Off, None, ambient_transition)?
+            }
+            AmbientSetting::Toggle => {
+                client.toggle(Light::Ambient)?;
+                toggled.push(Light::Ambient);
+            }
+            AmbientSetting::Adjust(adjustment) => {
+                adjust(client, Light::Ambient, adjustment, ambient_transition)?
+            }
+            AmbientSetting::Hsv { hue, sat, bright } => {
+                if scene_props(client, Light::Ambient, &[], ambient_transition)?.is_some() {
+                    client.set_scene(Light::Ambient, &Scene::Hsv { hue, sat, bright })?;
+                } else {
+                    client.set_power(Light::Ambient, Power::On, None, ambient_transition)?;
+                    client.set_hsv(Light::Ambient, hue, sat, ambient_transition)?;
+                    client.set_bright(Light::Ambient, bright, ambient_transition)?;
+                }
+            }
+            AmbientSetting::Rgb { rgb, bright } => {
+                let missing: Vec<&str> = bright
+                    .is_none()
+                    .then_some("bg_bright")
+                    .into_iter()
+                    .collect();
+                let scene = scene_props(client, Light::Ambient, &missing, ambient_transition)?
+                    .and_then(|current| {
+                        Some(Scene::Color {
+                            rgb,
+                            bright: bright.or(current.bg_bright)?,
+                        })
+                    });
+                if let Some(scene) = scene {
+                    client.set_scene(Light::Ambient, &scene)?;
+                } else {
+                    client.set_power(Light::Ambient, Power::On, None, ambient_transition)?;
+                    client.set_rgb(Light::Ambient, rgb, ambient_transition)?;
+                    if let Some(bright) = bright {
+                        client.set_bright(Light::Ambient, bright, ambient_transition)?;
+                    }
+                }
+            }
+        }
     }
-}
 
-#[derive(Debug, thiserror::Error)]
-enum HsvParseError {
-    #[error("invalid format: expected H,S,V or off")]
-    InvalidFormat,
-    #[error("invalid number: {0}")]
-    InvalidNumber(#[from] std::num::ParseIntError),
-    #[error("invalid hue: should be between 0 and 359")]
-    InvalidHue,
-    #[error("invalid saturation: should be between 0 and 100")]
-    InvalidSaturation,
-    #[error("invalid value: should be between 0 and 100")]
-    InvalidValue,
+    Ok(toggled)
 }
 
-fn parse_hsv(input: &str) -> Result<(u16, u8, u8), HsvParseError> {
-    if input == "off" {
-        return Ok((0, 0, 0));
+/// Returns the power state of the toggled lights, e.g. `main on, ambient off`. It is taken from
+/// the notifications the lamp sent, and read back for lights it hasn't sent one for yet.
+fn power_report(client: &mut Client, toggled: &[Light]) -> Result<Option<String>, CommandError> {
+    let mut main = None;
+    let mut ambient = None;
+    let notifications: Vec<_> = client.take_notifications().collect();
+    for notification in notifications {
+        log::debug!(
+            "Notification: {} {:?}",
+            notification.method,
+            notification.params
+        );
+        match notification.properties() {
+            Ok(props) => {
+                main = props.power.or(main);
+                ambient = props.bg_power.or(ambient);
+            }
+            Err(e) => log::debug!("Ignoring notification: {}", e),
+        }
+    }
+    if toggled.is_empty() {
+        return Ok(None);
     }
 
-    let parts: Vec<&str> = input.split(',').collect();
-    if parts.len() != 3 {
-        return Err(HsvParseError::InvalidFormat);
+    let missing: Vec<&str> = toggled
+        .iter()
+        .filter_map(|light| match light {
+            Light::Main if main.is_none() => Some("power"),
+            Light::Ambient if ambient.is_none() => Some("bg_power"),
+            _ => None,
+        })
+        .collect();
+    if !missing.is_empty() {
+        let props = Properties::from_values(&missing, &client.get_prop(&missing)?)?;
+        main = main.or(props.power);
+        ambient = ambient.or(props.bg_power);
     }
 
-    let h: u16 = parts[0].parse().map_err(HsvParseError::InvalidNumber)?;
-    let s: u8 = parts[1].parse().map_err(HsvParseError::InvalidNumber)?;
-    let v: u8 = parts[2].parse().map_err(HsvParseError::InvalidNumber)?;
+    let unknown = String::from("unknown");
+    Ok(Some(
+        toggled
+            .iter()
+            .map(|light| match light {
+                Light::Main => format!("main {}", main.map_or(unknown.clone(), |p| p.to_string())),
+                Light::Ambient => format!(
+                    "ambient {}",
+                    ambient.map_or(unknown.clone(), |p| p.to_string())
+                ),
+            })
+            .collect::<Vec<_>>()
+            .join(", "),
+    ))
+}
+
+/// Applies the settings to all targets at once, one thread per lamp. With several targets, a
+/// line per lamp reports the outcome.
+fn process(
+    targets: &[Target],
+    toggle_device: bool,
+    main: Option<&String>,
+    ambient: Option<&String>,
+    persist: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let main = main.map(|str| parse_main(str)).transpose()?;
+    let ambient = ambient.map(|str| parse_ambient(str)).transpose()?;
+    let main_changed = toggle_device || main.is_some();
+    let ambient_changed = toggle_device || ambient.is_some();
+    // Without any changes, --persist saves the current state of both lights.
+    let persisted: Vec<Light> = [
+        (Light::Main, main_changed || !ambient_changed),
+        (Light::Ambient, ambient_changed || !main_changed),
+    ]
+    .into_iter()
+    .filter(|&(_, saved)| persist && saved)
+    .map(|(light, _)| light)
+    .collect();
+
+    // Every thread connects first and waits for the others, so that the lamps change together.
+    let barrier = std::sync::Barrier::new(targets.len());
+    let mut results: Vec<Result<Option<String>, CommandError>> = std::thread::scope(|scope| {
+        let handles: Vec<_> = targets
+            .iter()
+            .map(|target| {
+                let barrier = &barrier;
+                let persisted = &persisted;
+                scope.spawn(move || {
+                    let client = Client::connect_with(&target.host, &target.config);
+                    barrier.wait();
+                    let mut client = client?;
+
+                    std::thread::sleep(std::time::Duration::from_millis(5));
+
+                    let toggled = apply(
+                        &mut client,
+                        toggle_device,
+                        main,
+                        ambient,
+                        target.main_transition,
+                        target.ambient_transition,
+                    )?;
 
-    if h > 359 {
-        return Err(HsvParseError::InvalidHue);
+                    if !persisted.is_empty() {
+                        // The lamp saves the state it is in at the moment, so let the
+                        // transitions finish first.
+                        let mut wait = std::time::Duration::ZERO;
+                        if main_changed {
+                            wait = wait.max(target.main_transition.duration());
+                        }
+                        if ambient_changed {
+                            wait = wait.max(target.ambient_transition.duration());
+                        }
+                        std::thread::sleep(wait);
+                        for &light in persisted {
+                            // The ambient light is saved without being asked for only if the
+                            // lamp has one.
+                            if light == Light::Ambient
+                                && ambient.is_none()
+                                && !has_ambient(&mut client)?
+                            {
+                                continue;
+                            }
+                            client.set_default(light)?;
+                        }
+                    }
+
+                    power_report(&mut client, &toggled)
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("lamp thread panicked"))
+            .collect()
+    });
+
+    if results.len() == 1 {
+        if let Some(report) = results.pop().expect("one result")? {
+            println!("{}", report);
+        }
+        return Ok(());
     }
-    if s > 100 {
-        return Err(HsvParseError::InvalidSaturation);
+
+    let mut failed = 0;
+    for (target, result) in targets.iter().zip(results) {
+        match result {
+            Ok(report) => println!("{}: {}", target.name, report.as_deref().unwrap_or("ok")),
+            Err(err) => {
+                failed += 1;
+                eprintln!("{}: Error: {}", target.name, err);
+            }
+        }
     }
-    if v > 100 {
-        return Err(HsvParseError::InvalidValue);
+    if failed > 0 {
+        return Err(format!("{} of {} lamps failed", failed, targets.len()).into());
     }
-
-    Ok((h, s, v))
+    Ok(())
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug)]
-pub struct Message {
-    id: u16,
-    method: String,
-    params: Vec<Param>,
-}
+fn process_discover(timeout: u64, json: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let devices = discover(std::time::Duration::from_secs(timeout))?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&devices)?);
+        return Ok(());
+    }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug)]
-#[serde(untagged)]
-enum Param {
-    Uint8(u8),
-    Uint16(u16),
-    Str(String),
+    for device in devices {
+        println!("{}:{}", device.host, device.port);
+        println!("  id: {}", device.id);
+        println!("  name: {}", device.name);
+        println!("  model: {}", device.model);
+        println!("  fw_ver: {}", device.fw_ver);
+        println!("  power: {}", device.power);
+        println!("  bright: {}", device.bright);
+        println!("  support: {}", device.support.join(" "));
+    }
+
+    Ok(())
 }
 
-#[derive(Debug)]
-struct Client {
-    stream: bufstream::BufStream<std::net::TcpStream>,
-    next_id: u16,
+fn process_status(
+    host: &str,
+    config: &ClientConfig,
+    json: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = Client::connect_with(host, config)?;
+    let props = client.get_properties()?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&props)?);
+    } else {
+        print!("{}", props);
+    }
+
+    Ok(())
 }
 
-fn connect_with_retries(
+fn process_timer(
     host: &str,
-    port: u16,
-    max_attempts: u32,
-    timeout: std::time::Duration,
-) -> std::io::Result<std::net::TcpStream> {
-    for attempt in 0..max_attempts {
-        let socket_addr = (host, port)
-            .to_socket_addrs()?
-            .next()
-            .expect("unable to resolve hostname");
-        match std::net::TcpStream::connect_timeout(&socket_addr, timeout) {
-            Ok(stream) => return Ok(stream),
-            Err(e) => {
-                log::debug!("Failed to connect to {}:{}: {}", host, port, e);
-                if attempt == max_attempts - 1 {
-                    return Err(e);
-                }
-            }
+    config: &ClientConfig,
+    duration: Option<std::time::Duration>,
+    cancel: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = Client::connect_with(host, config)?;
+
+    if cancel {
+        client.cancel_timer()?;
+    } else if let Some(duration) = duration {
+        // The lamp counts in minutes, round up so that the lamp doesn't turn off early.
+        let minutes = duration.as_nanos().div_ceil(60_000_000_000);
+        if !(1..=60).contains(&minutes) {
+            return Err("timer duration should be between 1 minute and 1 hour".into());
         }
+        client.set_timer(minutes as u32)?;
+    }
+
+    match client.get_timer()? {
+        Some(remaining) => println!("off in {}", humantime::format_duration(remaining)),
+        None => println!("no timer set"),
     }
-    unreachable!()
+
+    Ok(())
 }
 
-impl Client {
-    pub fn connect(host: &str, port: u16) -> std::io::Result<Self> {
-        log::debug!("Connecting to {}:{}...", host, port);
-        let start = std::time::Instant::now();
-        let tcp_stream =
-            connect_with_retries(host, port, 150 / 3, std::time::Duration::from_millis(300))?;
-        log::debug!("Connected in {:?}", start.elapsed());
-        tcp_stream
-            .set_read_timeout(Some(std::time::Duration::from_millis(200)))
-            .expect("set_read_timeout call failed");
-        tcp_stream
-            .set_write_timeout(Some(std::time::Duration::from_millis(200)))
-            .expect("set_write_timeout call failed");
-        let stream = bufstream::BufStream::new(tcp_stream);
-        Ok(Client { stream, next_id: 1 })
-    }
-
-    pub fn send_command(
-        &mut self,
-        method: &str,
-        params: Vec<Param>,
-    ) -> Result<String, Box<dyn std::error::Error>> {
-        let message = Message {
-            id: self.next_id,
-            method: method.to_string(),
-            params,
-        };
-        self.next_id += 1;
-        let json_message = serde_json::to_string(&message)?;
-        log::debug!("Sending: {}", json_message);
-        let start = std::time::Instant::now();
-        self.stream
-            .write_all(format!("{}\r\n", json_message).as_bytes())?;
-        self.stream.flush()?;
-
-        let mut bytes = Vec::new();
-        match self.stream.read_until(b'\n', &mut bytes) {
-            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                log::debug!("Re-sending: {}", json_message);
-                self.stream
-                    .write_all(format!("{}\r\n", json_message).as_bytes())?;
-                self.stream.flush()?;
-                self.stream.read_until(b'\n', &mut bytes)?;
-            }
-            Err(e) => return Err(Box::from(e)),
-            Ok(_) => {}
-        }
+fn process_snapshot(
+    host: &str,
+    config: &ClientConfig,
+    name: &str,
+    restore: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(format!("invalid snapshot name: {}", name).into());
+    }
+    let path = Config::snapshot_path(name)?;
 
-        let mut response = String::from_utf8(bytes)?;
-        response.truncate(response.trim_end().len());
-        log::debug!("Received (after {:?}): {}", start.elapsed(), response);
-        Ok(response)
+    if restore {
+        let snapshot = Snapshot::load(&path)?;
+        let mut client = Client::connect_with(host, config)?;
+        snapshot.restore(&mut client)?;
+    } else {
+        let mut client = Client::connect_with(host, config)?;
+        let snapshot = Snapshot::from_properties(&client.get_properties()?)?;
+        snapshot.save(&path)?;
     }
+
+    Ok(())
 }
 
-fn process(
-    host: &String,
-    port: u16,
-    main: Option<&String>,
-    ambient: Option<&String>,
+fn process_watch(
+    host: &str,
+    config: &ClientConfig,
+    json: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = Client::connect(host.as_str(), port)?;
-
-    std::thread::sleep(std::time::Duration::from_millis(5));
-
-    if let Some(str) = main {
-        let (mode, v) = parse_main(str)?;
-
-        if v == 0 {
-            client.send_command(
-                "set_power",
-                vec![
-                    Param::Str(String::from("off")),
-                    Param::Str(String::from("smooth")),
-                    Param::Uint16(500),
-                ],
-            )?;
+    let mut client = Client::connect_with(host, config)?;
+
+    loop {
+        let notification = client.next_notification()?;
+        if notification.method != "props" {
+            log::debug!("Ignoring notification: {}", notification.method);
+            continue;
+        }
+        let props = notification.properties()?;
+        let timestamp = humantime::format_rfc3339_millis(std::time::SystemTime::now());
+
+        if json {
+            println!(
+                "{}",
+                serde_json::json!({"timestamp": timestamp.to_string(), "props": props})
+            );
         } else {
-            client.send_command(
-                "set_power",
-                vec![
-                    Param::Str(String::from("on")),
-                    Param::Str(String::from("smooth")),
-                    Param::Uint16(500),
-                    Param::Uint8(mode as u8),
-                ],
-            )?;
-
-            client.send_command(
-                "set_bright",
-                vec![
-                    Param::Uint8(v),
-                    Param::Str(String::from("smooth")),
-                    Param::Uint16(500),
-                ],
-            )?;
+            println!(
+                "{} {}",
+                timestamp,
+                props.to_string().lines().collect::<Vec<_>>().join(", ")
+            );
         }
     }
+}
 
-    if let Some(str) = ambient {
-        let (h, s, v) = parse_hsv(str)?;
+fn process_flow(
+    host: &str,
+    config: &ClientConfig,
+    light: Light,
+    stop: bool,
+    steps: Vec<&String>,
+    repeat: u32,
+    action: FlowAction,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let flow = if stop {
+        None
+    } else {
+        Some(Flow {
+            repeat,
+            action,
+            steps: steps
+                .into_iter()
+                .map(|step| parse_flow_step(step))
+                .collect::<Result<_, _>>()?,
+        })
+    };
+    if let Some(flow) = &flow {
+        flow.validate()?;
+    }
 
-        if v == 0 {
-            client.send_command(
-                "bg_set_power",
-                vec![
-                    Param::Str(String::from("off")),
-                    Param::Str(String::from("smooth")),
-                    Param::Uint16(500),
-                ],
-            )?;
-        } else {
-            client.send_command(
-                "bg_set_power",
-                vec![
-                    Param::Str(String::from("on")),
-                    Param::Str(String::from("smooth")),
-                    Param::Uint16(500),
-                ],
-            )?;
-
-            client.send_command(
-                "bg_set_hsv",
-                vec![
-                    Param::Uint16(h),
-                    Param::Uint8(s),
-                    Param::Str(String::from("smooth")),
-                    Param::Uint16(500),
-                ],
-            )?;
-
-            client.send_command(
-                "bg_set_bright",
-                vec![
-                    Param::Uint8(v),
-                    Param::Str(String::from("smooth")),
-                    Param::Uint16(500),
-                ],
-            )?;
+    let mut client = Client::connect_with(host, config)?;
+    match flow {
+        Some(flow) => client.start_flow(light, &flow)?,
+        None => client.stop_flow(light)?,
+    }
+
+    Ok(())
+}
+
+/// Parses a timeout for the connection settings. Sockets don't accept a zero timeout.
+fn parse_timeout(
+    input: &str,
+) -> Result<std::time::Duration, Box<dyn std::error::Error + Send + Sync>> {
+    let timeout = parse_duration(input)?;
+    if timeout.is_zero() {
+        return Err("timeout should be greater than 0".into());
+    }
+    Ok(timeout)
+}
+
+fn client_config(matches: &clap::ArgMatches, device: Option<&DeviceConfig>) -> ClientConfig {
+    let mut config = ClientConfig::new();
+    if let Some(port) = device.and_then(|device| device.port) {
+        config = config.port(port);
+    }
+    if let Some(port) = matches.get_one::<u16>("port") {
+        config = config.port(*port);
+    }
+    if let Some(attempts) = matches.get_one::<u32>("connect-attempts") {
+        config = config.connect_attempts(*attempts);
+    }
+    if let Some(timeout) = matches.get_one::<std::time::Duration>("connect-timeout") {
+        config = config.connect_timeout(*timeout);
+    }
+    if let Some(timeout) = matches.get_one::<std::time::Duration>("read-timeout") {
+        config = config.read_timeout(*timeout);
+    }
+    if let Some(timeout) = matches.get_one::<std::time::Duration>("write-timeout") {
+        config = config.write_timeout(*timeout);
+    }
+    if matches.get_flag("fail-fast") {
+        config = config.fail_fast();
+    }
+    let mut rate_limit = RateLimit::new();
+    if let Some(path) = matches.get_one::<String>("rate-limit-file") {
+        rate_limit = rate_limit.state_file(path);
+    }
+    match matches.get_one::<String>("rate-limit").map(String::as_str) {
+        Some("off") => config.no_rate_limit(),
+        Some("reject") => config.rate_limit(rate_limit.policy(RateLimitPolicy::Reject)),
+        _ => config.rate_limit(rate_limit),
+    }
+}
+
+/// Loads the config file, treating an unknown config location as an empty config.
+fn load_config() -> Result<Config, ConfigError> {
+    match Config::load() {
+        Err(ConfigError::NoConfigDir) => Ok(Config::default()),
+        result => result,
+    }
+}
+
+/// Looks `name` up in the config file, then as a host name or address, and finally among the
+/// names stored on the lamps of the local network.
+fn resolve(
+    config: &Config,
+    name: &str,
+) -> Result<(String, Option<DeviceConfig>), Box<dyn std::error::Error>> {
+    if let Some(device) = config.devices.get(name) {
+        return Ok((device.host.clone(), Some(device.clone())));
+    }
+    if std::net::ToSocketAddrs::to_socket_addrs(&(name, 0)).is_ok() {
+        return Ok((name.to_string(), None));
+    }
+
+    log::debug!("Looking for a lamp named {}", name);
+    let mut devices = discover(std::time::Duration::from_secs(1))?
+        .into_iter()
+        .filter(|device| device.name == name);
+    match (devices.next(), devices.next()) {
+        (Some(device), None) => Ok((
+            device.host.clone(),
+            Some(DeviceConfig {
+                host: device.host,
+                port: Some(device.port),
+                model: Some(device.model),
+                effect: None,
+                duration: None,
+            }),
+        )),
+        (Some(_), Some(_)) => Err(format!("several lamps are named {}", name).into()),
+        // Let connecting report the unknown host.
+        (None, _) => Ok((name.to_string(), None)),
+    }
+}
+
+/// Expands the hosts given on the command line into lamps. Groups are replaced by their
+/// members and every lamp is only included once.
+fn targets(matches: &clap::ArgMatches) -> Result<Vec<Target>, Box<dyn std::error::Error>> {
+    let config = load_config()?;
+    let mut names: Vec<String> = Vec::new();
+    let hosts = matches
+        .get_many::<String>("host")
+        .into_iter()
+        .flatten()
+        .chain(matches.get_many::<String>("hosts").into_iter().flatten());
+    for host in hosts {
+        for name in config.expand(host) {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+    if names.is_empty() {
+        return Err("no lamps to control".into());
+    }
+
+    names
+        .into_iter()
+        .map(|name| {
+            let (host, device) = resolve(&config, &name)?;
+            let device = device.as_ref().map(|device| (name.as_str(), device));
+            Ok(Target {
+                host,
+                config: client_config(matches, device.map(|(_, device)| device)),
+                main_transition: transition(matches, "main", device)?,
+                ambient_transition: transition(matches, "ambient", device)?,
+                name,
+            })
+        })
+        .collect()
+}
+
+fn process_devices(matches: &clap::ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let mut config = Config::load()?;
+
+    match matches.subcommand() {
+        Some(("add", sub_matches)) => {
+            let name = sub_matches.get_one::<String>("name").expect("required");
+            let device = DeviceConfig {
+                host: sub_matches
+                    .get_one::<String>("host")
+                    .expect("required")
+                    .clone(),
+                port: sub_matches.get_one::<u16>("port").copied(),
+                model: sub_matches.get_one::<String>("model").cloned(),
+                effect: sub_matches.get_one::<String>("effect").map(|effect| {
+                    match effect.as_str() {
+                        "sudden" => Effect::Sudden,
+                        _ => Effect::Smooth,
+                    }
+                }),
+                duration: sub_matches.get_one::<String>("duration").cloned(),
+            };
+            device.transition(name)?;
+            config.devices.insert(name.clone(), device);
+            config.save()?;
+        }
+        Some(("group", sub_matches)) => {
+            let name = sub_matches.get_one::<String>("name").expect("required");
+            let members = sub_matches
+                .get_many::<String>("members")
+                .expect("required")
+                .cloned()
+                .collect();
+            config.groups.insert(name.clone(), members);
+            config.save()?;
+        }
+        Some(("remove", sub_matches)) => {
+            let name = sub_matches.get_one::<String>("name").expect("required");
+            if config.devices.remove(name).is_none() && config.groups.remove(name).is_none() {
+                return Err(format!("unknown device or group: {}", name).into());
+            }
+            config.save()?;
+        }
+        _ => {
+            let json = matches
+                .subcommand_matches("list")
+                .is_some_and(|list_matches| list_matches.get_flag("json"));
+            if json {
+                println!("{}", serde_json::to_string_pretty(&config.devices)?);
+                return Ok(());
+            }
+            for (name, device) in &config.devices {
+                print!("{}: {}", name, device.host);
+                if let Some(port) = device.port {
+                    print!(":{}", port);
+                }
+                if let Some(model) = &device.model {
+                    print!(" model={}", model);
+                }
+                if let Some(effect) = device.effect {
+                    print!(" effect={}", effect);
+                }
+                if let Some(duration) = &device.duration {
+                    print!(" duration={}", duration);
+                }
+                println!();
+            }
+            for (name, members) in &config.groups {
+                println!("{}: group {}", name, members.join(", "));
+            }
         }
     }
 
     Ok(())
 }
 
+fn run(matches: &clap::ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let lamp = |sub_matches: &clap::ArgMatches| -> Result<_, Box<dyn std::error::Error>> {
+        let (host, device) = resolve(
+            &load_config()?,
+            sub_matches.get_one::<String>("host").expect("required"),
+        )?;
+        let config = client_config(matches, device.as_ref());
+        Ok((host, config))
+    };
+
+    match matches.subcommand() {
+        Some(("discover", sub_matches)) => process_discover(
+            *sub_matches.get_one::<u64>("timeout").expect("default"),
+            sub_matches.get_flag("json"),
+        ),
+        Some(("devices", sub_matches)) => process_devices(sub_matches),
+        Some(("status", sub_matches)) => {
+            let (host, config) = lamp(sub_matches)?;
+            process_status(&host, &config, sub_matches.get_flag("json"))
+        }
+        Some(("watch", sub_matches)) => {
+            let (host, config) = lamp(sub_matches)?;
+            process_watch(&host, &config, sub_matches.get_flag("json"))
+        }
+        Some(("rename", sub_matches)) => {
+            let (host, config) = lamp(sub_matches)?;
+            let mut client = Client::connect_with(&host, &config)?;
+            client.set_name(sub_matches.get_one::<String>("name").expect("required"))?;
+            Ok(())
+        }
+        Some(("snapshot", sub_matches)) => {
+            let (action, sub_matches) = sub_matches.subcommand().expect("required");
+            let (host, config) = lamp(sub_matches)?;
+            process_snapshot(
+                &host,
+                &config,
+                sub_matches.get_one::<String>("name").expect("required"),
+                action == "restore",
+            )
+        }
+        Some(("timer", sub_matches)) => {
+            let (host, config) = lamp(sub_matches)?;
+            process_timer(
+                &host,
+                &config,
+                sub_matches
+                    .get_one::<std::time::Duration>("duration")
+                    .copied(),
+                sub_matches.get_flag("cancel"),
+            )
+        }
+        Some(("flow", sub_matches)) => {
+            let (host, config) = lamp(sub_matches)?;
+            process_flow(
+                &host,
+                &config,
+                if sub_matches.get_flag("ambient") {
+                    Light::Ambient
+                } else {
+                    Light::Main
+                },
+                sub_matches.get_flag("stop"),
+                sub_matches
+                    .get_many::<String>("steps")
+                    .map(|steps| steps.collect())
+                    .unwrap_or_default(),
+                *sub_matches.get_one::<u32>("repeat").expect("default"),
+                match sub_matches
+                    .get_one::<String>("end")
+                    .expect("default")
+                    .as_str()
+                {
+                    "stay" => FlowAction::Stay,
+                    "off" => FlowAction::Off,
+                    _ => FlowAction::Recover,
+                },
+            )
+        }
+        _ => process(
+            &targets(matches)?,
+            matches.get_flag("toggle"),
+            matches.get_one::<String>("main"),
+            matches.get_one::<String>("ambient"),
+            matches.get_flag("persist"),
+        ),
+    }
+}
+
 fn main() -> std::process::ExitCode {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
@@ -286,27 +812,332 @@ fn main() -> std::process::ExitCode {
         .arg(
             clap::Arg::new("main")
                 .long("main")
-                .value_name("X|off|moonlight:V|normal:V")
-                .help("Set main light (X is between 0 and 200, V is between 1 and 100)"),
+                .value_name("X|off|toggle|moonlight:V|normal:V[@K]|ct:K")
+                .allow_hyphen_values(true)
+                .help(
+                    "Set main light (X is between 0 and 200, V is between 1 and 100, \
+                     K is between 1700 and 6500), or change it with +N, -N% or ct:+K",
+                ),
         )
         .arg(
             clap::Arg::new("ambient")
                 .long("ambient")
-                .value_name("H,S,V|off")
-                .help("Set ambient light"),
+                .value_name("H,S,V|#RRGGBB[@V]|rgb:R,G,B[@V]|NAME[@V]|off|toggle")
+                .allow_hyphen_values(true)
+                .help(
+                    "Set ambient light (NAME is a color like warmwhite or teal), \
+                     or change it with +N, -N% or ct:+K",
+                ),
+        )
+        .arg(
+            clap::Arg::new("toggle")
+                .long("toggle")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with_all(["main", "ambient"])
+                .help("Toggle the main and the ambient light together"),
+        )
+        .arg(
+            clap::Arg::new("persist")
+                .long("persist")
+                .action(clap::ArgAction::SetTrue)
+                .help(
+                    "Save the resulting state of the changed lights (or of both lights if \
+                     nothing is changed) as the state after a power cut",
+                ),
+        )
+        .arg(
+            clap::Arg::new("effect")
+                .long("effect")
+                .value_name("sudden|smooth")
+                .value_parser(["sudden", "smooth"])
+                .help("Transition effect (default: smooth)"),
+        )
+        .arg(
+            clap::Arg::new("duration")
+                .long("duration")
+                .value_name("DURATION")
+                .value_parser(parse_duration)
+                .help("Transition duration, e.g. 2s or 1500ms (default: 500ms)"),
+        )
+        .arg(
+            clap::Arg::new("main-effect")
+                .long("main-effect")
+                .value_name("sudden|smooth")
+                .value_parser(["sudden", "smooth"])
+                .help("Transition effect for the main light"),
+        )
+        .arg(
+            clap::Arg::new("main-duration")
+                .long("main-duration")
+                .value_name("DURATION")
+                .value_parser(parse_duration)
+                .help("Transition duration for the main light"),
+        )
+        .arg(
+            clap::Arg::new("ambient-effect")
+                .long("ambient-effect")
+                .value_name("sudden|smooth")
+                .value_parser(["sudden", "smooth"])
+                .help("Transition effect for the ambient light"),
+        )
+        .arg(
+            clap::Arg::new("ambient-duration")
+                .long("ambient-duration")
+                .value_name("DURATION")
+                .value_parser(parse_duration)
+                .help("Transition duration for the ambient light"),
+        )
+        .arg(
+            clap::Arg::new("host")
+                .value_name("DEVICE|GROUP|HOST")
+                .required_unless_present("hosts")
+                .help("A device or group name from the config file, or a host name or address"),
+        )
+        .arg(
+            clap::Arg::new("hosts")
+                .long("host")
+                .value_name("DEVICE|GROUP|HOST")
+                .action(clap::ArgAction::Append)
+                .help("Another lamp to control at the same time (can be repeated)"),
+        )
+        .arg(
+            clap::Arg::new("port")
+                .long("port")
+                .value_parser(clap::value_parser!(u16))
+                .global(true)
+                .help("Lamp port (default: 55443)"),
         )
-        .arg(clap::Arg::new("host").required(true))
+        .arg(
+            clap::Arg::new("connect-attempts")
+                .long("connect-attempts")
+                .value_name("N")
+                .value_parser(clap::value_parser!(u32))
+                .global(true)
+                .help("How many times to try connecting (default: 50)"),
+        )
+        .arg(
+            clap::Arg::new("connect-timeout")
+                .long("connect-timeout")
+                .value_name("DURATION")
+                .value_parser(parse_timeout)
+                .global(true)
+                .help("Timeout of a single connection attempt (default: 300ms)"),
+        )
+        .arg(
+            clap::Arg::new("read-timeout")
+                .long("read-timeout")
+                .value_name("DURATION")
+                .value_parser(parse_timeout)
+                .global(true)
+                .help("How long to wait for a reply before re-sending (default: 200ms)"),
+        )
+        .arg(
+            clap::Arg::new("write-timeout")
+                .long("write-timeout")
+                .value_name("DURATION")
+                .value_parser(parse_timeout)
+                .global(true)
+                .help("Timeout for sending a command (default: 200ms)"),
+        )
+        .arg(
+            clap::Arg::new("fail-fast")
+                .long("fail-fast")
+                .action(clap::ArgAction::SetTrue)
+                .global(true)
+                .help("Give up after the first failed connection attempt"),
+        )
+        .arg(
+            clap::Arg::new("rate-limit")
+                .long("rate-limit")
+                .value_name("wait|reject|off")
+                .value_parser(["wait", "reject", "off"])
+                .global(true)
+                .help("What to do when the budget of 60 commands per minute is used up (default: wait)"),
+        )
+        .arg(
+            clap::Arg::new("rate-limit-file")
+                .long("rate-limit-file")
+                .value_name("FILE")
+                .global(true)
+                .help("Share the command budget with other processes through FILE"),
+        )
+        .subcommand(
+            clap::Command::new("discover")
+                .about("Find lamps on the local network")
+                .arg(
+                    clap::Arg::new("timeout")
+                        .long("timeout")
+                        .value_name("SECONDS")
+                        .value_parser(clap::value_parser!(u64))
+                        .default_value("2")
+                        .help("How long to wait for replies"),
+                )
+                .arg(
+                    clap::Arg::new("json")
+                        .long("json")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Print the lamps as JSON"),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("devices")
+                .about("Manage named devices in the config file")
+                .subcommand(
+                    clap::Command::new("list")
+                        .about("List configured devices (the default)")
+                        .arg(
+                            clap::Arg::new("json")
+                                .long("json")
+                                .action(clap::ArgAction::SetTrue)
+                                .help("Print the devices as JSON"),
+                        ),
+                )
+                .subcommand(
+                    clap::Command::new("add")
+                        .about("Add or replace a device (use --port for a non-default port)")
+                        .arg(clap::Arg::new("name").required(true))
+                        .arg(clap::Arg::new("host").required(true))
+                        .arg(clap::Arg::new("model").long("model").help("Lamp model"))
+                        .arg(
+                            clap::Arg::new("effect")
+                                .long("effect")
+                                .value_name("sudden|smooth")
+                                .value_parser(["sudden", "smooth"])
+                                .help("Default transition effect"),
+                        )
+                        .arg(
+                            clap::Arg::new("duration")
+                                .long("duration")
+                                .value_name("DURATION")
+                                .help("Default transition duration"),
+                        ),
+                )
+                .subcommand(
+                    clap::Command::new("group")
+                        .about("Add or replace a group of lamps that are controlled together")
+                        .arg(clap::Arg::new("name").required(true))
+                        .arg(
+                            clap::Arg::new("members")
+                                .value_name("DEVICE|HOST")
+                                .num_args(1..)
+                                .required(true),
+                        ),
+                )
+                .subcommand(
+                    clap::Command::new("remove")
+                        .about("Remove a device or group")
+                        .arg(clap::Arg::new("name").required(true)),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("status")
+                .about("Show the current state of a lamp")
+                .arg(clap::Arg::new("host").required(true))
+                .arg(
+                    clap::Arg::new("json")
+                        .long("json")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Print the properties as JSON"),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("watch")
+                .about("Print property changes as the lamp reports them")
+                .arg(clap::Arg::new("host").required(true))
+                .arg(
+                    clap::Arg::new("json")
+                        .long("json")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Print one JSON object per line"),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("rename")
+                .about("Store a name on a lamp, which can then be used instead of its address")
+                .arg(clap::Arg::new("host").required(true))
+                .arg(clap::Arg::new("name").required(true)),
+        )
+        .subcommand(
+            clap::Command::new("snapshot")
+                .about("Save the state of a lamp to a file, or restore it")
+                .subcommand_required(true)
+                .subcommand(
+                    clap::Command::new("save")
+                        .about("Save the current state of a lamp as NAME")
+                        .arg(clap::Arg::new("name").required(true))
+                        .arg(clap::Arg::new("host").required(true)),
+                )
+                .subcommand(
+                    clap::Command::new("restore")
+                        .about("Put a lamp back into the state saved as NAME")
+                        .arg(clap::Arg::new("name").required(true))
+                        .arg(clap::Arg::new("host").required(true)),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("timer")
+                .about("Set, show or cancel the power-off timer of a lamp")
+                .arg(clap::Arg::new("host").required(true))
+                .arg(
+                    clap::Arg::new("duration")
+                        .value_name("DURATION")
+                        .value_parser(parse_duration)
+                        .help("Turn the lamp off after DURATION, e.g. 30m (up to 1h, rounded up to whole minutes)"),
+                )
+                .arg(
+                    clap::Arg::new("cancel")
+                        .long("cancel")
+                        .action(clap::ArgAction::SetTrue)
+                        .conflicts_with("duration")
+                        .help("Cancel the timer"),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("flow")
+                .about("Start or stop a color flow")
+                .arg(clap::Arg::new("host").required(true))
+                .arg(
+                    clap::Arg::new("steps")
+                        .value_name("STEP")
+                        .num_args(1..)
+                        .required_unless_present("stop")
+                        .help("rgb:RRGGBB[@B]/DURATION, ct:K[@B]/DURATION or sleep:DURATION"),
+                )
+                .arg(
+                    clap::Arg::new("ambient")
+                        .long("ambient")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Run the flow on the ambient light"),
+                )
+                .arg(
+                    clap::Arg::new("repeat")
+                        .long("repeat")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(u32))
+                        .default_value("0")
+                        .help("How many times to play the steps, 0 means forever"),
+                )
+                .arg(
+                    clap::Arg::new("end")
+                        .long("end")
+                        .value_name("recover|stay|off")
+                        .value_parser(["recover", "stay", "off"])
+                        .default_value("recover")
+                        .help("What to do when the flow ends"),
+                )
+                .arg(
+                    clap::Arg::new("stop")
+                        .long("stop")
+                        .action(clap::ArgAction::SetTrue)
+                        .conflicts_with("steps")
+                        .help("Stop the running flow"),
+                ),
+        )
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
         .get_matches();
 
-    let host = matches.get_one::<String>("host").expect("required");
-    let port: u16 = 55443;
-
-    match process(
-        host,
-        port,
-        matches.get_one::<String>("main"),
-        matches.get_one::<String>("ambient"),
-    ) {
+    match run(&matches) {
         Err(err) => {
             eprintln!("Error: {}", err);
             std::process::ExitCode::from(1)
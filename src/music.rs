@@ -0,0 +1,118 @@
+use std::{
+    io::Write,
+    net::{TcpListener, TcpStream},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    client::{Client, CommandError},
+    commands::Commands,
+    protocol::{Message, Param},
+};
+
+/// How long to wait for the lamp to connect back after `set_music`.
+const ACCEPT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A music mode connection to a lamp.
+///
+/// In music mode the lamp connects back to a server we run, and commands sent over that
+/// connection are not rate limited. The lamp doesn't reply to them, so [`Commands`] methods
+/// return as soon as the command is written.
+///
+/// Music mode is turned off with `set_music 0` when the session is stopped or dropped.
+#[derive(Debug)]
+pub struct MusicSession {
+    client: Option<Client>,
+    stream: TcpStream,
+    next_id: u16,
+}
+
+fn accept_with_timeout(listener: &TcpListener, timeout: Duration) -> std::io::Result<TcpStream> {
+    listener.set_nonblocking(true)?;
+    let deadline = Instant::now() + timeout;
+    loop {
+        match listener.accept() {
+            Ok((stream, addr)) => {
+                log::debug!("Lamp connected from {}", addr);
+                stream.set_nonblocking(false)?;
+                return Ok(stream);
+            }
+            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                if Instant::now() >= deadline {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        "lamp did not connect back for music mode",
+                    ));
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+impl MusicSession {
+    /// Listens on the address the lamp already reaches us on, asks the lamp to connect to it
+    /// with `set_music 1` and waits for the connection.
+    pub fn start(mut client: Client) -> Result<Self, CommandError> {
+        let local_ip = client.local_addr()?.ip();
+        let listener = TcpListener::bind((local_ip, 0))?;
+        let port = listener.local_addr()?.port();
+        log::debug!("Waiting for music mode connection on {}:{}", local_ip, port);
+
+        client.send_command(
+            "set_music",
+            vec![
+                Param::Uint8(1),
+                Param::Str(local_ip.to_string()),
+                Param::Uint16(port),
+            ],
+        )?;
+        let stream = accept_with_timeout(&listener, ACCEPT_TIMEOUT)?;
+        stream.set_nodelay(true)?;
+
+        Ok(MusicSession {
+            client: Some(client),
+            stream,
+            next_id: 1,
+        })
+    }
+
+    /// Turns music mode off and returns the original connection.
+    pub fn stop(mut self) -> Result<Client, CommandError> {
+        let mut client = self.client.take().expect("client is only taken on stop");
+        client.send_command("set_music", vec![Param::Uint8(0)])?;
+        Ok(client)
+    }
+}
+
+impl Drop for MusicSession {
+    fn drop(&mut self) {
+        if let Some(client) = self.client.as_mut() {
+            if let Err(e) = client.send_command("set_music", vec![Param::Uint8(0)]) {
+                log::debug!("Failed to turn music mode off: {}", e);
+            }
+        }
+    }
+}
+
+impl Commands for MusicSession {
+    fn send_command(
+        &mut self,
+        method: &str,
+        params: Vec<Param>,
+    ) -> Result<Vec<serde_json::Value>, CommandError> {
+        let message = Message {
+            id: self.next_id,
+            method: method.to_string(),
+            params,
+        };
+        // Ids start at 1, so 0 is skipped when they wrap around.
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        let json_message = serde_json::to_string(&message)?;
+        log::debug!("Sending (music mode): {}", json_message);
+        self.stream
+            .write_all(format!("{}\r\n", json_message).as_bytes())?;
+        Ok(vec![])
+    }
+}
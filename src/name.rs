@@ -0,0 +1,102 @@
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn base64_decode(input: &str) -> Option<Vec<u8>> {
+    let input = input.as_bytes();
+    if input.is_empty() || !input.len().is_multiple_of(4) {
+        return None;
+    }
+    let mut out = Vec::with_capacity(input.len() / 4 * 3);
+    for (i, chunk) in input.chunks(4).enumerate() {
+        let last = i == input.len() / 4 - 1;
+        let padding = chunk.iter().rev().take_while(|&&c| c == b'=').count();
+        if padding > 2 || (padding > 0 && !last) {
+            return None;
+        }
+        let mut n = 0u32;
+        for &c in &chunk[..4 - padding] {
+            let value = ALPHABET.iter().position(|&a| a == c)?;
+            n = n << 6 | value as u32;
+        }
+        n <<= 6 * padding;
+        out.extend_from_slice(&n.to_be_bytes()[1..4 - padding]);
+    }
+    Some(out)
+}
+
+/// Encodes a name for `set_name`. ASCII names are sent as they are, other names
+/// base64-encoded, which is how lamps and their apps exchange them.
+pub fn encode_name(name: &str) -> String {
+    if name.is_ascii() {
+        name.to_string()
+    } else {
+        base64_encode(name.as_bytes())
+    }
+}
+
+/// Decodes a name reported by a lamp. Names that don't decode to non-ASCII text are returned
+/// unchanged.
+pub fn decode_name(name: &str) -> String {
+    base64_decode(name)
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .filter(|decoded| !decoded.is_ascii())
+        .unwrap_or_else(|| name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_padding() {
+        assert_eq!(base64_encode(b"abc"), "YWJj");
+        assert_eq!(base64_encode(b"ab"), "YWI=");
+        assert_eq!(base64_encode(b"a"), "YQ==");
+        assert_eq!(base64_decode("YWJj").as_deref(), Some(&b"abc"[..]));
+        assert_eq!(base64_decode("YWI=").as_deref(), Some(&b"ab"[..]));
+        assert_eq!(base64_decode("YQ==").as_deref(), Some(&b"a"[..]));
+    }
+
+    #[test]
+    fn base64_invalid() {
+        assert_eq!(base64_decode(""), None);
+        assert_eq!(base64_decode("YWJ"), None);
+        assert_eq!(base64_decode("Y==="), None);
+        assert_eq!(base64_decode("YQ==YWJj"), None);
+        assert_eq!(base64_decode("YW!j"), None);
+    }
+
+    #[test]
+    fn non_ascii_round_trip() {
+        let encoded = encode_name("Настольная лампа");
+        assert!(encoded.is_ascii());
+        assert_eq!(decode_name(&encoded), "Настольная лампа");
+    }
+
+    #[test]
+    fn ascii_names_are_unchanged() {
+        assert_eq!(encode_name("Desk lamp"), "Desk lamp");
+        // Valid base64, but decodes to "abc" and to invalid UTF-8.
+        assert_eq!(decode_name("YWJj"), "YWJj");
+        assert_eq!(decode_name("test"), "test");
+    }
+}
@@ -0,0 +1,530 @@
+use crate::protocol::{Mode, CT_RANGE};
+
+#[derive(Debug, thiserror::Error)]
+#[allow(clippy::enum_variant_names)]
+pub enum MainParseError {
+    #[error("invalid format: expected X or moonlight:V or normal:V[@K] or ct:K or off or toggle")]
+    InvalidFormat,
+    #[error("invalid number: {0}")]
+    InvalidNumber(#[from] std::num::ParseIntError),
+    #[error("invalid value: should be between 0 and 100")]
+    InvalidValue,
+    #[error("invalid color temperature: should be between 1700 and 6500")]
+    InvalidColorTemperature,
+    #[error("invalid color temperature: moonlight mode has a fixed color temperature")]
+    InvalidColorTemperatureMode,
+    #[error("{0}")]
+    InvalidAdjustment(#[from] AdjustmentParseError),
+}
+
+/// The state requested for the main light.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MainSetting {
+    Off,
+    /// Turn the light off if it is on and on if it is off.
+    Toggle,
+    /// Turn the light on in `mode`. Brightness and color temperature are left unchanged when
+    /// they are `None`.
+    On {
+        mode: Mode,
+        bright: Option<u8>,
+        ct: Option<u16>,
+    },
+    /// Change the brightness or color temperature relative to the current value.
+    Adjust(Adjustment),
+}
+
+fn parse_ct(input: &str) -> Result<u16, MainParseError> {
+    let ct: u16 = input
+        .strip_suffix('K')
+        .unwrap_or(input)
+        .parse()
+        .map_err(MainParseError::InvalidNumber)?;
+    if !CT_RANGE.contains(&ct) {
+        return Err(MainParseError::InvalidColorTemperature);
+    }
+    Ok(ct)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AdjustmentParseError {
+    #[error("invalid format: expected +N, -N%, ct:+K or ct:-N%")]
+    InvalidFormat,
+    #[error("invalid number: {0}")]
+    InvalidNumber(#[from] std::num::ParseIntError),
+    #[error("invalid adjustment: should be at most 100% (4800K for color temperature)")]
+    OutOfRange,
+}
+
+/// A change relative to the current state of a light.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Adjustment {
+    /// Change the brightness by this many points (-100-100).
+    Bright(i8),
+    /// Change the color temperature by this percentage (-100-100) of [`CT_RANGE`].
+    Ct(i8),
+}
+
+fn is_adjustment(input: &str) -> bool {
+    let input = input.strip_prefix("ct:").unwrap_or(input);
+    input.starts_with('+') || input.starts_with('-')
+}
+
+/// Parses a relative change: `+N` or `-N` changes the brightness by N points (`+N%` means the
+/// same), `ct:+K` or `ct:-K` changes the color temperature by K Kelvin and `ct:+N%` by N
+/// percent of [`CT_RANGE`].
+pub fn parse_adjustment(input: &str) -> Result<Adjustment, AdjustmentParseError> {
+    let (ct, input) = match input.strip_prefix("ct:") {
+        Some(input) => (true, input),
+        None => (false, input),
+    };
+    let (negative, number) = if let Some(number) = input.strip_prefix('+') {
+        (false, number)
+    } else if let Some(number) = input.strip_prefix('-') {
+        (true, number)
+    } else {
+        return Err(AdjustmentParseError::InvalidFormat);
+    };
+    // Reject a second sign, which `parse` would accept.
+    if !number.starts_with(|c: char| c.is_ascii_digit()) {
+        return Err(AdjustmentParseError::InvalidFormat);
+    }
+
+    let percentage = match number.strip_suffix('%') {
+        Some(number) => number.parse::<u16>()?,
+        None if ct => {
+            let kelvin: u32 = number.strip_suffix('K').unwrap_or(number).parse()?;
+            let range = u32::from(CT_RANGE.end() - CT_RANGE.start());
+            if kelvin > range {
+                return Err(AdjustmentParseError::OutOfRange);
+            }
+            // Round to the nearest percent, but don't let a small change round to nothing.
+            ((kelvin * 100 + range / 2) / range).max(u32::from(kelvin > 0)) as u16
+        }
+        None => number.parse::<u16>()?,
+    };
+    if percentage > 100 {
+        return Err(AdjustmentParseError::OutOfRange);
+    }
+    let percentage = if negative {
+        -(percentage as i8)
+    } else {
+        percentage as i8
+    };
+
+    Ok(if ct {
+        Adjustment::Ct(percentage)
+    } else {
+        Adjustment::Bright(percentage)
+    })
+}
+
+/// Parses the `--main` syntax: `X` (0..=100 is moonlight, 101..=200 is normal), `off`,
+/// `toggle`, `moonlight:V`, `normal:V` or `ct:K`. `X` and `normal:V` may be followed by `@K`
+/// to also set the color temperature, e.g. `normal:80@2700K`. A brightness of 0 turns the
+/// light off. Relative changes are accepted as described in [`parse_adjustment`].
+pub fn parse_main(input: &str) -> Result<MainSetting, MainParseError> {
+    if input == "off" {
+        return Ok(MainSetting::Off);
+    }
+    if input == "toggle" {
+        return Ok(MainSetting::Toggle);
+    }
+
+    if is_adjustment(input) {
+        return Ok(MainSetting::Adjust(parse_adjustment(input)?));
+    }
+
+    if let Some(ct) = input.strip_prefix("ct:") {
+        return Ok(MainSetting::On {
+            mode: Mode::Normal,
+            bright: None,
+            ct: Some(parse_ct(ct)?),
+        });
+    }
+
+    let (input, ct) = match input.split_once('@') {
+        Some((input, ct)) => (input, Some(parse_ct(ct)?)),
+        None => (input, None),
+    };
+
+    let (mode, v) = if let Ok(v) = input.parse::<u8>() {
+        match v {
+            0..=100 => (Mode::Moonlight, v),
+            101..=200 => (Mode::Normal, v - 100),
+            _ => return Err(MainParseError::InvalidFormat),
+        }
+    } else {
+        let parts: Vec<&str> = input.split(':').collect();
+        if parts.len() != 2 {
+            return Err(MainParseError::InvalidFormat);
+        }
+
+        let v: u8 = parts[1].parse().map_err(MainParseError::InvalidNumber)?;
+        if v > 100 {
+            return Err(MainParseError::InvalidValue);
+        }
+        match parts[0] {
+            "moonlight" => (Mode::Moonlight, v),
+            "normal" => (Mode::Normal, v),
+            _ => return Err(MainParseError::InvalidValue),
+        }
+    };
+
+    if v == 0 {
+        return Ok(MainSetting::Off);
+    }
+    if mode == Mode::Moonlight && ct.is_some() {
+        return Err(MainParseError::InvalidColorTemperatureMode);
+    }
+    Ok(MainSetting::On {
+        mode,
+        bright: Some(v),
+        ct,
+    })
+}
+
+#[derive(Debug, thiserror::Error)]
+#[allow(clippy::enum_variant_names)]
+pub enum HsvParseError {
+    #[error("invalid format: expected H,S,V or off")]
+    InvalidFormat,
+    #[error("invalid number: {0}")]
+    InvalidNumber(#[from] std::num::ParseIntError),
+    #[error("invalid hue: should be between 0 and 359")]
+    InvalidHue,
+    #[error("invalid saturation: should be between 0 and 100")]
+    InvalidSaturation,
+    #[error("invalid value: should be between 0 and 100")]
+    InvalidValue,
+}
+
+/// Parses the `--ambient` syntax: `H,S,V` or `off`. A value of 0 means the light should be
+/// turned off.
+pub fn parse_hsv(input: &str) -> Result<(u16, u8, u8), HsvParseError> {
+    if input == "off" {
+        return Ok((0, 0, 0));
+    }
+
+    let parts: Vec<&str> = input.split(',').collect();
+    if parts.len() != 3 {
+        return Err(HsvParseError::InvalidFormat);
+    }
+
+    let h: u16 = parts[0].parse().map_err(HsvParseError::InvalidNumber)?;
+    let s: u8 = parts[1].parse().map_err(HsvParseError::InvalidNumber)?;
+    let v: u8 = parts[2].parse().map_err(HsvParseError::InvalidNumber)?;
+
+    if h > 359 {
+        return Err(HsvParseError::InvalidHue);
+    }
+    if s > 100 {
+        return Err(HsvParseError::InvalidSaturation);
+    }
+    if v > 100 {
+        return Err(HsvParseError::InvalidValue);
+    }
+
+    Ok((h, s, v))
+}
+
+/// Colors accepted by name in the `--ambient` syntax.
+pub const NAMED_COLORS: [(&str, u32); 13] = [
+    ("red", 0xff0000),
+    ("orange", 0xff8800),
+    ("yellow", 0xffff00),
+    ("green", 0x00ff00),
+    ("teal", 0x008080),
+    ("cyan", 0x00ffff),
+    ("blue", 0x0000ff),
+    ("purple", 0x800080),
+    ("magenta", 0xff00ff),
+    ("pink", 0xffc0cb),
+    ("white", 0xffffff),
+    ("warmwhite", 0xffb46b),
+    ("coldwhite", 0xdbe9ff),
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum AmbientParseError {
+    #[error(
+        "invalid format: expected H,S,V or #RRGGBB or rgb:R,G,B or a color name or off or toggle"
+    )]
+    InvalidFormat,
+    #[error("invalid number: {0}")]
+    InvalidNumber(#[from] std::num::ParseIntError),
+    #[error("invalid value: should be between 0 and 100")]
+    InvalidValue,
+    #[error("{0}")]
+    InvalidHsv(#[from] HsvParseError),
+    #[error("{0}")]
+    InvalidAdjustment(#[from] AdjustmentParseError),
+}
+
+/// The state requested for the ambient light.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AmbientSetting {
+    Off,
+    /// Turn the light off if it is on and on if it is off.
+    Toggle,
+    Hsv {
+        hue: u16,
+        sat: u8,
+        bright: u8,
+    },
+    /// An RGB color; brightness is left unchanged when it is `None`.
+    Rgb {
+        rgb: u32,
+        bright: Option<u8>,
+    },
+    /// Change the brightness or color temperature relative to the current value.
+    Adjust(Adjustment),
+}
+
+/// Parses the `--ambient` syntax: `H,S,V`, `#RRGGBB`, `rgb:R,G,B`, a name from
+/// [`NAMED_COLORS`], `off` or `toggle`. RGB forms may be followed by `@V` to also set the
+/// brightness, e.g. `teal@40`. A value of 0 or black turns the light off. Relative changes
+/// are accepted as described in [`parse_adjustment`].
+pub fn parse_ambient(input: &str) -> Result<AmbientSetting, AmbientParseError> {
+    if input == "toggle" {
+        return Ok(AmbientSetting::Toggle);
+    }
+    if is_adjustment(input) {
+        return Ok(AmbientSetting::Adjust(parse_adjustment(input)?));
+    }
+
+    let (color, bright) = match input.split_once('@') {
+        Some((color, bright)) => {
+            if !bright.bytes().all(|c| c.is_ascii_digit()) {
+                return Err(AmbientParseError::InvalidFormat);
+            }
+            let v: u8 = bright.parse().map_err(AmbientParseError::InvalidNumber)?;
+            if v > 100 {
+                return Err(AmbientParseError::InvalidValue);
+            }
+            (color, Some(v))
+        }
+        None => (input, None),
+    };
+
+    let rgb = if let Some(components) = color.strip_prefix("rgb:") {
+        let parts: Vec<&str> = components.split(',').collect();
+        if parts.len() != 3 {
+            return Err(AmbientParseError::InvalidFormat);
+        }
+        let mut rgb = 0u32;
+        for part in parts {
+            if !part.bytes().all(|c| c.is_ascii_digit()) {
+                return Err(AmbientParseError::InvalidFormat);
+            }
+            let c: u8 = part.parse().map_err(AmbientParseError::InvalidNumber)?;
+            rgb = rgb << 8 | u32::from(c);
+        }
+        Some(rgb)
+    } else if color.starts_with('#') {
+        Some(parse_hex_color(color).ok_or(AmbientParseError::InvalidFormat)?)
+    } else {
+        NAMED_COLORS
+            .iter()
+            .find(|(name, _)| *name == color)
+            .map(|&(_, rgb)| rgb)
+    };
+
+    match (rgb, bright) {
+        (Some(0), _) | (Some(_), Some(0)) => Ok(AmbientSetting::Off),
+        (Some(rgb), bright) => Ok(AmbientSetting::Rgb { rgb, bright }),
+        (None, Some(_)) => Err(AmbientParseError::InvalidFormat),
+        (None, None) => match parse_hsv(input).map_err(|e| match e {
+            HsvParseError::InvalidFormat => AmbientParseError::InvalidFormat,
+            e => AmbientParseError::InvalidHsv(e),
+        })? {
+            (_, _, 0) => Ok(AmbientSetting::Off),
+            (hue, sat, bright) => Ok(AmbientSetting::Hsv { hue, sat, bright }),
+        },
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[allow(clippy::enum_variant_names)]
+pub enum DurationParseError {
+    #[error("invalid format: expected N, Nms, Ns, Nm, Nh or a combination like 1h30m")]
+    InvalidFormat,
+    #[error("invalid number: {0}")]
+    InvalidNumber(#[from] std::num::ParseIntError),
+    #[error("duration is too long")]
+    TooLong,
+}
+
+/// Parses a duration such as `1500ms`, `2s`, `10m`, `1h` or a combination like `1m30s`. A bare
+/// number is taken as milliseconds.
+pub fn parse_duration(input: &str) -> Result<std::time::Duration, DurationParseError> {
+    if input.is_empty() {
+        return Err(DurationParseError::InvalidFormat);
+    }
+    if input.bytes().all(|c| c.is_ascii_digit()) {
+        return Ok(std::time::Duration::from_millis(input.parse()?));
+    }
+
+    let mut total = std::time::Duration::ZERO;
+    let mut rest = input;
+    while !rest.is_empty() {
+        let split = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let (number, tail) = rest.split_at(split);
+        let split = tail
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(split);
+        if number.is_empty() {
+            return Err(DurationParseError::InvalidFormat);
+        }
+        let n: u64 = number.parse()?;
+
+        let part = match unit {
+            "ms" => Some(std::time::Duration::from_millis(n)),
+            "s" => Some(std::time::Duration::from_secs(n)),
+            "m" => n.checked_mul(60).map(std::time::Duration::from_secs),
+            "h" => n.checked_mul(60 * 60).map(std::time::Duration::from_secs),
+            _ => return Err(DurationParseError::InvalidFormat),
+        }
+        .ok_or(DurationParseError::TooLong)?;
+        total = total.checked_add(part).ok_or(DurationParseError::TooLong)?;
+        rest = tail;
+    }
+    Ok(total)
+}
+
+/// Parses a 24-bit color written as `RRGGBB` with an optional leading `#`.
+pub(crate) fn parse_hex_color(input: &str) -> Option<u32> {
+    let hex = input.strip_prefix('#').unwrap_or(input);
+    if hex.len() != 6 || !hex.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    u32::from_str_radix(hex, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn ambient_colors() {
+        assert_eq!(
+            parse_ambient("#00ff80").unwrap(),
+            AmbientSetting::Rgb {
+                rgb: 0x00ff80,
+                bright: None
+            }
+        );
+        assert_eq!(
+            parse_ambient("rgb:255,0,16").unwrap(),
+            AmbientSetting::Rgb {
+                rgb: 0xff0010,
+                bright: None
+            }
+        );
+        assert_eq!(
+            parse_ambient("teal").unwrap(),
+            AmbientSetting::Rgb {
+                rgb: 0x008080,
+                bright: None
+            }
+        );
+        assert_eq!(
+            parse_ambient("teal@40").unwrap(),
+            AmbientSetting::Rgb {
+                rgb: 0x008080,
+                bright: Some(40)
+            }
+        );
+        assert_eq!(
+            parse_ambient("10,20,30").unwrap(),
+            AmbientSetting::Hsv {
+                hue: 10,
+                sat: 20,
+                bright: 30
+            }
+        );
+    }
+
+    #[test]
+    fn ambient_off() {
+        assert_eq!(parse_ambient("off").unwrap(), AmbientSetting::Off);
+        assert_eq!(parse_ambient("#000000").unwrap(), AmbientSetting::Off);
+        assert_eq!(parse_ambient("rgb:0,0,0").unwrap(), AmbientSetting::Off);
+        assert_eq!(parse_ambient("#000000@50").unwrap(), AmbientSetting::Off);
+        assert_eq!(parse_ambient("red@0").unwrap(), AmbientSetting::Off);
+    }
+
+    #[test]
+    fn malformed_ambient() {
+        for input in [
+            "#+12345",
+            "#12345",
+            "#1234567",
+            "#gg0000",
+            "rgb:+255,0,0",
+            "rgb:255,0",
+            "rgb:256,0,0",
+            "red@+40",
+            "red@101",
+            "purple-ish",
+            "",
+        ] {
+            assert!(parse_ambient(input).is_err(), "{:?} was accepted", input);
+        }
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("0").unwrap(), Duration::ZERO);
+        assert_eq!(parse_duration("29ms").unwrap(), Duration::from_millis(29));
+        assert_eq!(parse_duration("1500").unwrap(), Duration::from_millis(1500));
+        assert_eq!(parse_duration("2s").unwrap(), Duration::from_secs(2));
+        assert_eq!(parse_duration("10m").unwrap(), Duration::from_secs(600));
+        assert_eq!(parse_duration("1h").unwrap(), Duration::from_secs(3600));
+        assert_eq!(parse_duration("1h30m").unwrap(), Duration::from_secs(5400));
+        assert_eq!(
+            parse_duration("1s500ms").unwrap(),
+            Duration::from_millis(1500)
+        );
+    }
+
+    #[test]
+    fn invalid_durations() {
+        assert!(matches!(
+            parse_duration("307445734561825861m"),
+            Err(DurationParseError::TooLong)
+        ));
+        assert!(matches!(
+            parse_duration("99999999999999999999"),
+            Err(DurationParseError::InvalidNumber(_))
+        ));
+        assert!(matches!(
+            parse_duration("2d"),
+            Err(DurationParseError::InvalidFormat)
+        ));
+        assert!(matches!(
+            parse_duration("1h30"),
+            Err(DurationParseError::InvalidFormat)
+        ));
+        assert!(matches!(
+            parse_duration("1m 30s"),
+            Err(DurationParseError::InvalidFormat)
+        ));
+        assert!(matches!(
+            parse_duration("5124095576030431h1h"),
+            Err(DurationParseError::TooLong)
+        ));
+        assert!(matches!(
+            parse_duration("s"),
+            Err(DurationParseError::InvalidFormat)
+        ));
+        assert!(matches!(
+            parse_duration(""),
+            Err(DurationParseError::InvalidFormat)
+        ));
+    }
+}
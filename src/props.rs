@@ -0,0 +1,249 @@
+use std::fmt;
+
+use crate::{
+    name::decode_name,
+    protocol::{Mode, Power},
+};
+
+/// The properties requested by [`crate::Client::get_properties`], in the order they are
+/// requested.
+pub const PROPERTY_NAMES: [&str; 21] = [
+    "power",
+    "bright",
+    "ct",
+    "rgb",
+    "hue",
+    "sat",
+    "color_mode",
+    "flowing",
+    "delayoff",
+    "music_on",
+    "name",
+    "bg_power",
+    "bg_bright",
+    "bg_hue",
+    "bg_sat",
+    "bg_ct",
+    "bg_rgb",
+    "bg_lmode",
+    "bg_flowing",
+    "nl_br",
+    "active_mode",
+];
+
+/// The color mode of a light.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorMode {
+    Rgb = 1,
+    Ct = 2,
+    Hsv = 3,
+}
+
+impl fmt::Display for ColorMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ColorMode::Rgb => "rgb",
+            ColorMode::Ct => "ct",
+            ColorMode::Hsv => "hsv",
+        })
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PropertyError {
+    #[error("expected {expected} values, got {actual}")]
+    WrongCount { expected: usize, actual: usize },
+    #[error("invalid value for {name}: {value:?}")]
+    InvalidValue { name: String, value: String },
+}
+
+/// Lamp properties as reported by `get_prop`.
+///
+/// Lamps return an empty string for properties they don't support; those are left as `None`.
+#[derive(serde::Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Properties {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub power: Option<Power>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bright: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ct: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rgb: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hue: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sat: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color_mode: Option<ColorMode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flowing: Option<bool>,
+    /// Minutes until the lamp turns itself off, 0 if no timer is set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delayoff: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub music_on: Option<bool>,
+    /// The name stored on the lamp, decoded with [`crate::decode_name`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bg_power: Option<Power>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bg_bright: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bg_hue: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bg_sat: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bg_ct: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bg_rgb: Option<u32>,
+    /// The color mode of the ambient light.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bg_lmode: Option<ColorMode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bg_flowing: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nl_br: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub active_mode: Option<Mode>,
+}
+
+fn parse_number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, PropertyError> {
+    value.parse().map_err(|_| PropertyError::InvalidValue {
+        name: name.to_string(),
+        value: value.to_string(),
+    })
+}
+
+fn parse_bool(name: &str, value: &str) -> Result<bool, PropertyError> {
+    match value {
+        "0" => Ok(false),
+        "1" => Ok(true),
+        _ => Err(PropertyError::InvalidValue {
+            name: name.to_string(),
+            value: value.to_string(),
+        }),
+    }
+}
+
+fn parse_power(name: &str, value: &str) -> Result<Power, PropertyError> {
+    match value {
+        "on" => Ok(Power::On),
+        "off" => Ok(Power::Off),
+        _ => Err(PropertyError::InvalidValue {
+            name: name.to_string(),
+            value: value.to_string(),
+        }),
+    }
+}
+
+fn parse_color_mode(name: &str, value: &str) -> Result<ColorMode, PropertyError> {
+    match value {
+        "1" => Ok(ColorMode::Rgb),
+        "2" => Ok(ColorMode::Ct),
+        "3" => Ok(ColorMode::Hsv),
+        _ => Err(PropertyError::InvalidValue {
+            name: name.to_string(),
+            value: value.to_string(),
+        }),
+    }
+}
+
+impl Properties {
+    /// Decodes the positional `get_prop` results for `names`.
+    pub fn from_values(names: &[&str], values: &[String]) -> Result<Self, PropertyError> {
+        if names.len() != values.len() {
+            return Err(PropertyError::WrongCount {
+                expected: names.len(),
+                actual: values.len(),
+            });
+        }
+        let mut props = Properties::default();
+        for (name, value) in names.iter().zip(values) {
+            props.set(name, value)?;
+        }
+        Ok(props)
+    }
+
+    /// Decodes a single property. Empty values and unknown names are ignored.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), PropertyError> {
+        if value.is_empty() {
+            return Ok(());
+        }
+        match name {
+            "power" => self.power = Some(parse_power(name, value)?),
+            "bright" => self.bright = Some(parse_number(name, value)?),
+            "ct" => self.ct = Some(parse_number(name, value)?),
+            "rgb" => self.rgb = Some(parse_number(name, value)?),
+            "hue" => self.hue = Some(parse_number(name, value)?),
+            "sat" => self.sat = Some(parse_number(name, value)?),
+            "color_mode" => self.color_mode = Some(parse_color_mode(name, value)?),
+            "flowing" => self.flowing = Some(parse_bool(name, value)?),
+            "delayoff" => self.delayoff = Some(parse_number(name, value)?),
+            "music_on" => self.music_on = Some(parse_bool(name, value)?),
+            "name" => self.name = Some(decode_name(value)),
+            "bg_power" => self.bg_power = Some(parse_power(name, value)?),
+            "bg_bright" => self.bg_bright = Some(parse_number(name, value)?),
+            "bg_hue" => self.bg_hue = Some(parse_number(name, value)?),
+            "bg_sat" => self.bg_sat = Some(parse_number(name, value)?),
+            "bg_ct" => self.bg_ct = Some(parse_number(name, value)?),
+            "bg_rgb" => self.bg_rgb = Some(parse_number(name, value)?),
+            "bg_lmode" => self.bg_lmode = Some(parse_color_mode(name, value)?),
+            "bg_flowing" => self.bg_flowing = Some(parse_bool(name, value)?),
+            "nl_br" => self.nl_br = Some(parse_number(name, value)?),
+            "active_mode" => {
+                self.active_mode = Some(match value {
+                    "0" => Mode::Normal,
+                    "1" => Mode::Moonlight,
+                    _ => {
+                        return Err(PropertyError::InvalidValue {
+                            name: name.to_string(),
+                            value: value.to_string(),
+                        })
+                    }
+                })
+            }
+            _ => log::debug!("Ignoring unknown property {}", name),
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Properties {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn line<T: fmt::Display>(
+            f: &mut fmt::Formatter<'_>,
+            name: &str,
+            value: &Option<T>,
+        ) -> fmt::Result {
+            match value {
+                Some(value) => writeln!(f, "{}: {}", name, value),
+                None => Ok(()),
+            }
+        }
+
+        line(f, "power", &self.power)?;
+        line(f, "bright", &self.bright)?;
+        line(f, "ct", &self.ct.map(|ct| format!("{}K", ct)))?;
+        line(f, "rgb", &self.rgb.map(|rgb| format!("#{:06x}", rgb)))?;
+        line(f, "hue", &self.hue)?;
+        line(f, "sat", &self.sat)?;
+        line(f, "color_mode", &self.color_mode)?;
+        line(f, "flowing", &self.flowing)?;
+        line(f, "delayoff", &self.delayoff.map(|m| format!("{} min", m)))?;
+        line(f, "music_on", &self.music_on)?;
+        line(f, "name", &self.name)?;
+        line(f, "bg_power", &self.bg_power)?;
+        line(f, "bg_bright", &self.bg_bright)?;
+        line(f, "bg_hue", &self.bg_hue)?;
+        line(f, "bg_sat", &self.bg_sat)?;
+        line(f, "bg_ct", &self.bg_ct.map(|ct| format!("{}K", ct)))?;
+        line(f, "bg_rgb", &self.bg_rgb.map(|rgb| format!("#{:06x}", rgb)))?;
+        line(f, "bg_lmode", &self.bg_lmode)?;
+        line(f, "bg_flowing", &self.bg_flowing)?;
+        line(f, "nl_br", &self.nl_br)?;
+        line(f, "active_mode", &self.active_mode)
+    }
+}
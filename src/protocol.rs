@@ -0,0 +1,321 @@
+use crate::props::{Properties, PropertyError};
+
+/// A command sent to the lamp.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct Message {
+    pub id: u16,
+    pub method: String,
+    pub params: Vec<Param>,
+}
+
+/// A single positional parameter of a [`Message`].
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[serde(untagged)]
+pub enum Param {
+    Uint8(u8),
+    Uint16(u16),
+    Uint32(u32),
+    Int32(i32),
+    Str(String),
+}
+
+/// The error object the lamp sends when it rejects a command.
+#[derive(serde::Deserialize, Debug)]
+pub struct DeviceError {
+    pub code: i32,
+    pub message: String,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Outcome {
+    Result(Vec<serde_json::Value>),
+    Error(DeviceError),
+}
+
+/// A reply to a [`Message`] with the same id.
+#[derive(serde::Deserialize, Debug)]
+pub struct Response {
+    pub id: u16,
+    #[serde(flatten)]
+    pub(crate) outcome: Outcome,
+}
+
+impl Response {
+    /// Returns the `result` array, or the `error` object if the lamp rejected the command.
+    pub fn into_result(self) -> Result<Vec<serde_json::Value>, DeviceError> {
+        match self.outcome {
+            Outcome::Result(result) => Ok(result),
+            Outcome::Error(error) => Err(error),
+        }
+    }
+}
+
+/// An unsolicited message from the lamp, e.g. a `props` update after a state change.
+#[derive(serde::Deserialize, Debug)]
+pub struct Notification {
+    pub method: String,
+    pub params: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Notification {
+    /// Decodes the parameters of a `props` notification. Only the properties that changed are
+    /// set.
+    pub fn properties(&self) -> Result<Properties, PropertyError> {
+        let mut props = Properties::default();
+        for (name, value) in &self.params {
+            match value {
+                serde_json::Value::String(value) => props.set(name, value)?,
+                value => props.set(name, &value.to_string())?,
+            }
+        }
+        Ok(props)
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(untagged)]
+pub(crate) enum Incoming {
+    Response(Response),
+    Notification(Notification),
+}
+
+/// An entry of the `cron_get` reply.
+#[derive(serde::Deserialize, Debug)]
+pub(crate) struct CronJob {
+    /// Minutes left until the job runs.
+    pub delay: u64,
+}
+
+/// Color temperatures in Kelvin accepted by the lamps.
+pub const CT_RANGE: std::ops::RangeInclusive<u16> = 1700..=6500;
+
+/// The mode the main light is switched into when it is turned on.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    Normal = 1,
+    Moonlight = 5,
+}
+
+/// Which light of the lamp a command addresses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Light {
+    Main,
+    Ambient,
+}
+
+impl Light {
+    /// Returns the protocol method name for this light, adding the `bg_` prefix for the
+    /// ambient light.
+    pub fn method(self, name: &str) -> String {
+        match self {
+            Light::Main => name.to_string(),
+            Light::Ambient => format!("bg_{}", name),
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Power {
+    On,
+    Off,
+}
+
+impl Power {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Power::On => "on",
+            Power::Off => "off",
+        }
+    }
+}
+
+impl std::fmt::Display for Power {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The direction of a `set_adjust` change.
+#[derive(serde::Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AdjustAction {
+    Increase,
+    Decrease,
+    /// Increase, wrapping around to the minimum after the maximum.
+    Circle,
+}
+
+impl AdjustAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AdjustAction::Increase => "increase",
+            AdjustAction::Decrease => "decrease",
+            AdjustAction::Circle => "circle",
+        }
+    }
+}
+
+/// The property changed by `set_adjust`. Only [`AdjustAction::Circle`] applies to
+/// [`AdjustProp::Color`].
+#[derive(serde::Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AdjustProp {
+    Bright,
+    Ct,
+    Color,
+}
+
+impl AdjustProp {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AdjustProp::Bright => "bright",
+            AdjustProp::Ct => "ct",
+            AdjustProp::Color => "color",
+        }
+    }
+}
+
+impl std::fmt::Display for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Mode::Normal => "normal",
+            Mode::Moonlight => "moonlight",
+        })
+    }
+}
+
+/// How a light changes to a new state.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Effect {
+    Sudden,
+    Smooth,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TransitionError {
+    #[error("invalid duration: smooth transitions should take at least 30ms")]
+    DurationTooShort,
+    #[error("invalid duration: should be at most {} ms", u32::MAX)]
+    DurationTooLong,
+}
+
+/// The effect and duration sent with every state change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transition {
+    effect: Effect,
+    duration: std::time::Duration,
+}
+
+impl Transition {
+    /// The shortest smooth transition the lamps accept.
+    pub const MIN_DURATION: std::time::Duration = std::time::Duration::from_millis(30);
+
+    /// Creates a transition. A zero duration is turned into a sudden change.
+    pub fn new(effect: Effect, duration: std::time::Duration) -> Result<Self, TransitionError> {
+        if duration.is_zero() {
+            return Ok(Transition::sudden());
+        }
+        if effect == Effect::Smooth && duration < Self::MIN_DURATION {
+            return Err(TransitionError::DurationTooShort);
+        }
+        if duration.as_millis() > u32::MAX as u128 {
+            return Err(TransitionError::DurationTooLong);
+        }
+        Ok(Transition { effect, duration })
+    }
+
+    pub fn sudden() -> Self {
+        Transition {
+            effect: Effect::Sudden,
+            duration: Self::MIN_DURATION,
+        }
+    }
+
+    pub fn effect(&self) -> Effect {
+        self.effect
+    }
+
+    pub fn duration(&self) -> std::time::Duration {
+        self.duration
+    }
+
+    /// Returns the `effect, duration` parameter pair.
+    pub(crate) fn params(&self) -> [Param; 2] {
+        let effect = match self.effect {
+            Effect::Sudden => "sudden",
+            Effect::Smooth => "smooth",
+        };
+        [
+            Param::Str(String::from(effect)),
+            Param::Uint32(self.duration.as_millis() as u32),
+        ]
+    }
+}
+
+impl std::fmt::Display for Effect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Effect::Sudden => "sudden",
+            Effect::Smooth => "smooth",
+        })
+    }
+}
+
+impl Default for Transition {
+    fn default() -> Self {
+        Transition {
+            effect: Effect::Smooth,
+            duration: std::time::Duration::from_millis(500),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn zero_duration_is_sudden() {
+        let transition = Transition::new(Effect::Smooth, Duration::ZERO).unwrap();
+        assert_eq!(transition.effect(), Effect::Sudden);
+    }
+
+    #[test]
+    fn smooth_transitions_have_a_minimum() {
+        assert!(matches!(
+            Transition::new(Effect::Smooth, Duration::from_millis(29)),
+            Err(TransitionError::DurationTooShort)
+        ));
+        let transition = Transition::new(Effect::Sudden, Duration::from_millis(29)).unwrap();
+        assert_eq!(transition.duration(), Duration::from_millis(29));
+        let transition = Transition::new(Effect::Smooth, Duration::from_millis(30)).unwrap();
+        assert_eq!(transition.duration(), Duration::from_millis(30));
+    }
+
+    #[test]
+    fn transition_params() {
+        let transition = Transition::new(Effect::Smooth, Duration::from_secs(2)).unwrap();
+        assert_eq!(
+            serde_json::to_value(transition.params()).unwrap(),
+            serde_json::json!(["smooth", 2000])
+        );
+        let transition = Transition::new(Effect::Smooth, Duration::from_secs(600)).unwrap();
+        assert_eq!(transition.duration(), Duration::from_secs(600));
+    }
+
+    #[test]
+    fn long_transitions_are_rejected() {
+        assert!(matches!(
+            Transition::new(
+                Effect::Smooth,
+                Duration::from_millis(u64::from(u32::MAX) + 1)
+            ),
+            Err(TransitionError::DurationTooLong)
+        ));
+    }
+}
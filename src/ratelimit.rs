@@ -0,0 +1,182 @@
+use std::{
+    collections::BTreeMap,
+    io::{Read, Seek, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+/// What to do when a command would exceed the budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitPolicy {
+    /// Sleep until the command fits into the budget.
+    Wait,
+    /// Fail with [`RateLimitError::Exhausted`].
+    Reject,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RateLimitError {
+    #[error("rate limit exhausted: next command allowed in {retry_after:?}")]
+    Exhausted { retry_after: Duration },
+    #[error("unable to access rate limit state: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid rate limit state: {0}")]
+    InvalidState(#[from] serde_json::Error),
+}
+
+/// A token bucket that keeps the number of commands sent to a lamp under its quota.
+///
+/// Budgets are tracked per lamp and shared by all clients in the process. With a state file,
+/// they are also shared with other processes; the file is locked while it is updated.
+///
+/// ```
+/// use std::time::Duration;
+///
+/// let rate_limit = yeelight::RateLimit::new()
+///     .commands(30)
+///     .period(Duration::from_secs(60))
+///     .policy(yeelight::RateLimitPolicy::Reject);
+/// ```
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+    commands: u32,
+    period: Duration,
+    policy: RateLimitPolicy,
+    state_file: Option<PathBuf>,
+}
+
+impl Default for RateLimit {
+    /// 60 commands per minute, waiting when the budget is exhausted.
+    fn default() -> Self {
+        RateLimit {
+            commands: 60,
+            period: Duration::from_secs(60),
+            policy: RateLimitPolicy::Wait,
+            state_file: None,
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    /// Seconds since the Unix epoch.
+    updated: f64,
+}
+
+static BUCKETS: Mutex<BTreeMap<String, Bucket>> = Mutex::new(BTreeMap::new());
+
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+impl RateLimit {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how many commands may be sent per period. Values below 1 are treated as 1.
+    pub fn commands(mut self, commands: u32) -> Self {
+        self.commands = commands.max(1);
+        self
+    }
+
+    /// Sets the period the budget refills over. Values below 1ms are treated as 1ms.
+    pub fn period(mut self, period: Duration) -> Self {
+        self.period = period.max(Duration::from_millis(1));
+        self
+    }
+
+    pub fn policy(mut self, policy: RateLimitPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Shares the budget with other processes through the file at `path`.
+    pub fn state_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.state_file = Some(path.into());
+        self
+    }
+
+    fn full_bucket(&self) -> Bucket {
+        Bucket {
+            tokens: f64::from(self.commands),
+            updated: now(),
+        }
+    }
+
+    /// Refills the bucket and takes a token from it, or returns how long to wait for one.
+    fn take(&self, bucket: &mut Bucket) -> Result<(), Duration> {
+        let capacity = f64::from(self.commands);
+        let rate = capacity / self.period.as_secs_f64();
+        let now = now();
+        bucket.tokens = (bucket.tokens + (now - bucket.updated).max(0.0) * rate).min(capacity);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+        }
+    }
+
+    fn take_local(&self, key: &str) -> Result<(), Duration> {
+        let mut buckets = BUCKETS.lock().unwrap();
+        let bucket = buckets
+            .entry(key.to_string())
+            .or_insert_with(|| self.full_bucket());
+        self.take(bucket)
+    }
+
+    fn take_shared(&self, path: &Path, key: &str) -> Result<Result<(), Duration>, RateLimitError> {
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        file.lock()?;
+
+        let mut data = String::new();
+        file.read_to_string(&mut data)?;
+        let mut buckets: BTreeMap<String, Bucket> = if data.trim().is_empty() {
+            BTreeMap::new()
+        } else {
+            serde_json::from_str(&data)?
+        };
+        let bucket = buckets
+            .entry(key.to_string())
+            .or_insert_with(|| self.full_bucket());
+        let result = self.take(bucket);
+
+        file.set_len(0)?;
+        file.rewind()?;
+        file.write_all(serde_json::to_string(&buckets)?.as_bytes())?;
+        Ok(result)
+    }
+
+    /// Takes one command from the budget of the lamp identified by `key`, waiting or failing
+    /// according to the policy if there is none left.
+    pub fn acquire(&self, key: &str) -> Result<(), RateLimitError> {
+        loop {
+            let result = match &self.state_file {
+                Some(path) => self.take_shared(path, key)?,
+                None => self.take_local(key),
+            };
+            match (result, self.policy) {
+                (Ok(()), _) => return Ok(()),
+                (Err(retry_after), RateLimitPolicy::Reject) => {
+                    return Err(RateLimitError::Exhausted { retry_after })
+                }
+                (Err(retry_after), RateLimitPolicy::Wait) => {
+                    log::debug!("Rate limit reached for {}, waiting {:?}", key, retry_after);
+                    std::thread::sleep(retry_after);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use crate::{
+    flow::{Flow, FlowError},
+    protocol::Param,
+};
+
+/// A state for `set_scene`, which turns the light on and applies the state in one command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Scene {
+    /// An RGB color (`0xRRGGBB`) at a brightness of 1-100.
+    Color { rgb: u32, bright: u8 },
+    /// A hue (0-359) and saturation (0-100) at a brightness of 1-100.
+    Hsv { hue: u16, sat: u8, bright: u8 },
+    /// A color temperature in Kelvin at a brightness of 1-100.
+    Ct { ct: u16, bright: u8 },
+    /// A color flow.
+    Flow(Flow),
+    /// A brightness of 1-100 with a timer that turns the light off after `minutes`.
+    AutoDelayOff { bright: u8, minutes: u32 },
+}
+
+impl Scene {
+    /// Returns the parameters of `set_scene`. Flows are checked against the protocol limits.
+    pub(crate) fn params(&self) -> Result<Vec<Param>, FlowError> {
+        Ok(match *self {
+            Scene::Color { rgb, bright } => vec![
+                Param::Str("color".to_string()),
+                Param::Uint32(rgb),
+                Param::Uint8(bright),
+            ],
+            Scene::Hsv { hue, sat, bright } => vec![
+                Param::Str("hsv".to_string()),
+                Param::Uint16(hue),
+                Param::Uint8(sat),
+                Param::Uint8(bright),
+            ],
+            Scene::Ct { ct, bright } => vec![
+                Param::Str("ct".to_string()),
+                Param::Uint16(ct),
+                Param::Uint8(bright),
+            ],
+            Scene::Flow(ref flow) => {
+                flow.validate()?;
+                vec![
+                    Param::Str("cf".to_string()),
+                    Param::Uint32(flow.count()?),
+                    Param::Uint8(flow.action as u8),
+                    Param::Str(flow.expression()),
+                ]
+            }
+            Scene::AutoDelayOff { bright, minutes } => vec![
+                Param::Str("auto_delay_off".to_string()),
+                Param::Uint8(bright),
+                Param::Uint32(minutes),
+            ],
+        })
+    }
+}
@@ -0,0 +1,185 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    client::CommandError,
+    commands::Commands,
+    props::{ColorMode, Properties},
+    protocol::{Light, Mode, Power, Transition},
+    scene::Scene,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum SnapshotError {
+    #[error("lamp didn't report {0}")]
+    MissingProperty(&'static str),
+    #[error("unable to access {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("invalid snapshot {path}: {source}")]
+    InvalidFormat {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+}
+
+/// The saved state of one light. Values the lamp doesn't report are left out.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LightSnapshot {
+    pub power: Power,
+    pub bright: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color_mode: Option<ColorMode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ct: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rgb: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hue: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sat: Option<u8>,
+    /// Whether a color flow was running. Lamps don't report the steps of a flow, so it is not
+    /// restarted on restore.
+    #[serde(default)]
+    pub flowing: bool,
+}
+
+impl LightSnapshot {
+    /// Returns the scene that brings the light back to the saved color mode, if the lamp
+    /// reported the values it needs.
+    fn scene(&self) -> Option<Scene> {
+        let bright = self.bright;
+        match (self.color_mode, self.rgb, self.hue, self.sat, self.ct) {
+            (Some(ColorMode::Rgb), Some(rgb), _, _, _) => Some(Scene::Color { rgb, bright }),
+            (Some(ColorMode::Hsv), _, Some(hue), Some(sat), _) => {
+                Some(Scene::Hsv { hue, sat, bright })
+            }
+            (_, _, _, _, Some(ct)) => Some(Scene::Ct { ct, bright }),
+            _ => None,
+        }
+    }
+}
+
+/// The state of a lamp that can be saved to a file and restored later.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub main: LightSnapshot,
+    #[serde(default = "default_mode")]
+    pub mode: Mode,
+    /// The brightness of the moonlight mode.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub moonlight_bright: Option<u8>,
+    /// Absent for lamps without an ambient light.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ambient: Option<LightSnapshot>,
+}
+
+fn default_mode() -> Mode {
+    Mode::Normal
+}
+
+impl Snapshot {
+    /// Builds a snapshot from the properties read with [`crate::Client::get_properties`].
+    pub fn from_properties(props: &Properties) -> Result<Self, SnapshotError> {
+        let main = LightSnapshot {
+            power: props.power.ok_or(SnapshotError::MissingProperty("power"))?,
+            bright: props
+                .bright
+                .ok_or(SnapshotError::MissingProperty("bright"))?,
+            color_mode: props.color_mode,
+            ct: props.ct,
+            rgb: props.rgb,
+            hue: props.hue,
+            sat: props.sat,
+            flowing: props.flowing.unwrap_or(false),
+        };
+        let ambient = match (props.bg_power, props.bg_bright) {
+            (Some(power), Some(bright)) => Some(LightSnapshot {
+                power,
+                bright,
+                color_mode: props.bg_lmode,
+                ct: props.bg_ct,
+                rgb: props.bg_rgb,
+                hue: props.bg_hue,
+                sat: props.bg_sat,
+                flowing: props.bg_flowing.unwrap_or(false),
+            }),
+            _ => None,
+        };
+        Ok(Snapshot {
+            main,
+            mode: props.active_mode.unwrap_or(Mode::Normal),
+            moonlight_bright: props.nl_br,
+            ambient,
+        })
+    }
+
+    /// Puts the lamp back into the saved state. Flows started since the snapshot are stopped,
+    /// lights that were on are restored with a single `set_scene` for their color mode and
+    /// lights that were off are just turned off.
+    pub fn restore(&self, lamp: &mut impl Commands) -> Result<(), CommandError> {
+        let transition = Transition::sudden();
+        if self.main.power == Power::On && !self.main.flowing {
+            lamp.stop_flow(Light::Main)?;
+        }
+        if self.main.power == Power::On && self.mode == Mode::Moonlight {
+            lamp.set_power(Light::Main, Power::On, Some(Mode::Moonlight), transition)?;
+            if let Some(bright) = self.moonlight_bright {
+                lamp.set_bright(Light::Main, bright, transition)?;
+            }
+        } else {
+            restore_light(lamp, Light::Main, &self.main)?;
+        }
+        if let Some(ambient) = &self.ambient {
+            if ambient.power == Power::On && !ambient.flowing {
+                lamp.stop_flow(Light::Ambient)?;
+            }
+            restore_light(lamp, Light::Ambient, ambient)?;
+        }
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, SnapshotError> {
+        let data = std::fs::read(path).map_err(|source| SnapshotError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        serde_json::from_slice(&data).map_err(|source| SnapshotError::InvalidFormat {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    /// Writes the snapshot as JSON, creating the directory if needed.
+    pub fn save(&self, path: &Path) -> Result<(), SnapshotError> {
+        let io_error = |source| SnapshotError::Io {
+            path: path.to_path_buf(),
+            source,
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(io_error)?;
+        }
+        let mut data = serde_json::to_string_pretty(self).expect("snapshot is serializable");
+        data.push('\n');
+        std::fs::write(path, data).map_err(io_error)
+    }
+}
+
+fn restore_light(
+    lamp: &mut impl Commands,
+    light: Light,
+    snapshot: &LightSnapshot,
+) -> Result<(), CommandError> {
+    let transition = Transition::sudden();
+    if snapshot.power == Power::Off {
+        return lamp.set_power(light, Power::Off, None, transition);
+    }
+    match snapshot.scene() {
+        Some(scene) => lamp.set_scene(light, &scene),
+        None => {
+            lamp.set_power(light, Power::On, None, transition)?;
+            lamp.set_bright(light, snapshot.bright, transition)
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use std::{process::Command, sync::Arc};
+
+use yeelight::emulator::{Emulator, LampState};
+
+/// Runs the CLI against a fresh emulator in `state` and returns the methods it sent.
+fn run(state: LampState, args: &[&str]) -> (Vec<String>, LampState) {
+    let emulator = Arc::new(Emulator::bind("127.0.0.1:0").unwrap());
+    emulator.set_state(state);
+    let port = emulator.local_addr().unwrap().port().to_string();
+    std::thread::spawn({
+        let emulator = Arc::clone(&emulator);
+        move || emulator.run()
+    });
+
+    let output = Command::new(env!("CARGO_BIN_EXE_yeelight"))
+        .args(["127.0.0.1", "--port", &port, "--rate-limit", "off"])
+        .args(args)
+        .env("YEELIGHT_CONFIG", "/nonexistent/yeelight.json")
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let methods = emulator
+        .received()
+        .into_iter()
+        .map(|(method, _)| method)
+        .collect();
+    (methods, emulator.state())
+}
+
+fn lamp(main_on: bool, ambient_on: bool) -> LampState {
+    let mut state = LampState::default();
+    state.main.power = main_on;
+    state.ambient.power = ambient_on;
+    state
+}
+
+#[test]
+fn light_that_is_off_is_turned_on_with_a_scene() {
+    let (methods, state) = run(lamp(false, false), &["--main", "normal:50"]);
+    assert_eq!(methods, ["get_prop", "set_scene"]);
+    assert!(state.main.power);
+    assert_eq!(state.main.bright, 50);
+
+    let (methods, state) = run(lamp(false, false), &["--ambient", "#ff0000"]);
+    assert_eq!(methods, ["get_prop", "bg_set_scene"]);
+    assert!(state.ambient.power);
+    assert_eq!(state.ambient.rgb, 0xff0000);
+}
+
+#[test]
+fn light_that_is_on_fades_to_the_new_state() {
+    let (methods, state) = run(lamp(true, true), &["--main", "normal:50"]);
+    assert_eq!(methods, ["get_prop", "set_power", "set_bright"]);
+    assert_eq!(state.main.bright, 50);
+
+    let (methods, state) = run(lamp(true, true), &["--ambient", "#ff0000"]);
+    assert_eq!(methods, ["get_prop", "bg_set_power", "bg_set_rgb"]);
+    assert_eq!(state.ambient.rgb, 0xff0000);
+}
+
+#[test]
+fn sudden_change_uses_a_scene_without_reading_the_lamp() {
+    let (methods, state) = run(
+        lamp(true, false),
+        &["--main", "normal:50@3000", "--effect", "sudden"],
+    );
+    assert_eq!(methods, ["set_scene"]);
+    assert_eq!((state.main.bright, state.main.ct), (50, 3000));
+
+    let (methods, state) = run(
+        lamp(true, false),
+        &["--main", "normal:50", "--effect", "sudden"],
+    );
+    assert_eq!(methods, ["get_prop", "set_scene"]);
+    assert_eq!((state.main.bright, state.main.ct), (50, 4000));
+}
@@ -0,0 +1,150 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use yeelight::{
+    emulator::{Emulator, Fault, Scenario},
+    Client, ClientConfig, CommandError, Commands, Light, MusicSession, Power, Transition,
+};
+
+fn start(scenario: Scenario) -> SocketAddr {
+    start_emulator(scenario).local_addr().unwrap()
+}
+
+fn start_emulator(scenario: Scenario) -> Arc<Emulator> {
+    let emulator = Arc::new(Emulator::bind("127.0.0.1:0").unwrap());
+    emulator.set_scenario(scenario);
+    std::thread::spawn({
+        let emulator = Arc::clone(&emulator);
+        move || emulator.run()
+    });
+    emulator
+}
+
+fn faults(commands: Vec<Fault>) -> Scenario {
+    Scenario {
+        commands,
+        ..Scenario::default()
+    }
+}
+
+fn config(addr: SocketAddr) -> ClientConfig {
+    ClientConfig::new()
+        .port(addr.port())
+        .connect_attempts(10)
+        .connect_timeout(Duration::from_millis(100))
+        .read_timeout(Duration::from_millis(200))
+        .no_rate_limit()
+}
+
+fn connect(addr: SocketAddr) -> Client {
+    Client::connect_with("127.0.0.1", &config(addr)).unwrap()
+}
+
+#[test]
+fn notifications_are_queued_while_waiting_for_replies() {
+    let mut client = connect(start(Scenario::default()));
+
+    client
+        .set_power(Light::Main, Power::On, None, Transition::sudden())
+        .unwrap();
+    // The reply to set_power comes before its notification, which is read while waiting for
+    // the reply to get_prop.
+    assert_eq!(client.get_prop(&["power"]).unwrap(), ["on"]);
+
+    let notifications: Vec<_> = client.take_notifications().collect();
+    assert_eq!(notifications.len(), 1);
+    assert_eq!(notifications[0].method, "props");
+    assert_eq!(notifications[0].params["power"], "on");
+}
+
+#[test]
+fn delayed_reply_is_resent() {
+    let mut client = connect(start(faults(vec![Fault::Delay { ms: 300 }])));
+    assert_eq!(client.get_prop(&["power"]).unwrap(), ["off"]);
+    // The reply to the re-sent command is dropped as stale.
+    assert_eq!(client.get_prop(&["bright"]).unwrap(), ["100"]);
+}
+
+#[test]
+fn dropped_reply_is_resent() {
+    let mut client = connect(start(faults(vec![Fault::Drop])));
+    assert_eq!(client.get_prop(&["power"]).unwrap(), ["off"]);
+}
+
+#[test]
+fn quota_error_is_a_device_error() {
+    let mut client = connect(start(faults(vec![Fault::QuotaExceeded])));
+    match client.get_prop(&["power"]) {
+        Err(CommandError::Device { code, message }) => {
+            assert_eq!(code, -1);
+            assert_eq!(message, "client quota exceeded");
+        }
+        result => panic!("expected a device error, got {:?}", result),
+    }
+}
+
+#[test]
+fn refused_connections_are_closed() {
+    let addr = start(Scenario {
+        refuse_connections: 2,
+        ..Scenario::default()
+    });
+    for _ in 0..2 {
+        let mut client = connect(addr);
+        assert!(client.get_prop(&["power"]).is_err());
+    }
+    let mut client = connect(addr);
+    assert_eq!(client.get_prop(&["power"]).unwrap(), ["off"]);
+}
+
+#[test]
+fn connect_gives_up_when_nothing_listens() {
+    let addr = Emulator::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let err = Client::connect_with("127.0.0.1", &config(addr)).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::ConnectionRefused);
+    let err = Client::connect_with("127.0.0.1", &config(addr).fail_fast()).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::ConnectionRefused);
+}
+
+#[test]
+fn zero_timeouts_are_clamped() {
+    let addr = start(Scenario::default());
+    let config = config(addr)
+        .connect_timeout(Duration::ZERO)
+        .read_timeout(Duration::ZERO)
+        .write_timeout(Duration::ZERO);
+    let mut client = Client::connect_with("127.0.0.1", &config).unwrap();
+    // The reply may not make it within 1ms, but the socket must accept the timeout.
+    match client.get_prop(&["power"]) {
+        Ok(_) => {}
+        Err(CommandError::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::WouldBlock),
+        Err(e) => panic!("unexpected error: {}", e),
+    }
+}
+
+#[test]
+fn music_session_is_turned_off_on_drop() {
+    let emulator = start_emulator(Scenario::default());
+    let client = connect(emulator.local_addr().unwrap());
+
+    let mut session = MusicSession::start(client).unwrap();
+    assert!(emulator.state().music_on);
+    session
+        .set_power(Light::Main, Power::On, None, Transition::sudden())
+        .unwrap();
+    // Music mode commands get no reply, so wait for the emulator to execute it.
+    let deadline = std::time::Instant::now() + Duration::from_secs(1);
+    while !emulator.state().main.power {
+        assert!(
+            std::time::Instant::now() < deadline,
+            "command wasn't executed"
+        );
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    drop(session);
+    assert!(!emulator.state().music_on);
+    let received = emulator.received();
+    let (method, params) = received.last().unwrap();
+    assert_eq!(method, "set_music");
+    assert_eq!(params, &[serde_json::json!(0)]);
+}